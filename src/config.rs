@@ -1,4 +1,7 @@
 pub mod cli;
+pub mod command;
 pub mod languagefile;
 pub mod runfile;
+pub mod settings;
 pub mod spaceworkfile;
+pub mod vars;
@@ -4,13 +4,30 @@ pub struct CliArg;
 
 impl CliArg {
     pub fn command() -> Arg<'static> {
-        Arg::new("command").value_name("COMMAND").exclusive(true)
+        Arg::new("command").value_name("COMMAND")
+    }
+
+    pub fn command_args() -> Arg<'static> {
+        Arg::new("args")
+            .about("Arguments passed on to COMMAND, or used as its ${args}")
+            .value_name("ARGS")
+            .multiple_values(true)
+            .last(true)
+            .requires("command")
+    }
+
+    pub fn dry_run() -> Arg<'static> {
+        Arg::new("dry-run")
+            .about("Show the commands COMMAND would run, without running them")
+            .long("dry-run")
+            .requires("command")
     }
 
     pub fn purge() -> Arg<'static> {
         Arg::new("purge")
             .about("Delete the spacework directory and history files")
             .long("purge")
+            .conflicts_with("command")
     }
 
     pub fn new_language() -> Arg<'static> {
@@ -29,21 +46,143 @@ impl CliArg {
         // .index(1)
     }
 
+    pub fn build_profile() -> Arg<'static> {
+        Arg::new("profile")
+            .about("Build with the named profile's flags [default: debug]")
+            .long("profile")
+            .short('p')
+            .value_name("NAME")
+            .takes_value(true)
+    }
+
+    pub fn build_release() -> Arg<'static> {
+        Arg::new("release")
+            .about("Build with the `release` profile")
+            .long("release")
+            .conflicts_with("profile")
+    }
+
+    pub fn build_jobs() -> Arg<'static> {
+        Arg::new("jobs")
+            .about("Compile N sources at once [default: number of CPUs]")
+            .long("jobs")
+            .short('j')
+            .value_name("N")
+            .takes_value(true)
+    }
+
+    pub fn run_args() -> Arg<'static> {
+        Arg::new("args")
+            .about("Arguments passed on to the program")
+            .value_name("ARGS")
+            .multiple_values(true)
+            .last(true)
+    }
+
+    pub fn resume_list() -> Arg<'static> {
+        Arg::new("list")
+            .about("List the projects most recently worked on")
+            .long("list")
+            .short('l')
+    }
+
+    pub fn resume_count() -> Arg<'static> {
+        Arg::new("count")
+            .about("List N projects [default: 10]")
+            .long("count")
+            .short('c')
+            .value_name("N")
+            .takes_value(true)
+            .requires("list")
+    }
+
     pub fn history_all() -> Arg<'static> {
         Arg::new("all")
             .about("View all spacework history")
             .long("all")
             .short('a')
-            .required_unless_present("count")
+            .conflicts_with("count")
     }
 
     pub fn history_count() -> Arg<'static> {
         Arg::new("count")
-            .about("View last N spacework commands used")
+            .about("View last N spacework commands used [default: 10]")
             .long("count")
             .short('c')
             .value_name("N")
             .takes_value(true)
-            .required_unless_present("all")
+    }
+
+    pub fn history_format() -> Arg<'static> {
+        Arg::new("format")
+            .about("Show history as text, JSON or CSV [default: text]")
+            .long("format")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .possible_values(&["text", "json", "csv"])
+    }
+
+    pub fn history_project() -> Arg<'static> {
+        Arg::new("project")
+            .about("Only history of the project with this name or path")
+            .long("project")
+            .short('p')
+            .value_name("PROJECT")
+            .takes_value(true)
+    }
+
+    pub fn history_since() -> Arg<'static> {
+        Arg::new("since")
+            .about("Only history from this time on, e.g. 2021-08-10 or 2d")
+            .long("since")
+            .value_name("TIME")
+            .takes_value(true)
+    }
+
+    pub fn history_until() -> Arg<'static> {
+        Arg::new("until")
+            .about("Only history up to this time, e.g. yesterday or 1w")
+            .long("until")
+            .value_name("TIME")
+            .takes_value(true)
+    }
+
+    pub fn history_action() -> Arg<'static> {
+        Arg::new("action")
+            .about("Only history of this kind of action")
+            .long("action")
+            .value_name("ACTION")
+            .takes_value(true)
+            .possible_values(&["new", "build", "run", "command", "other"])
+    }
+
+    pub fn history_failed() -> Arg<'static> {
+        Arg::new("failed")
+            .about("Only history of things that exited with an error")
+            .long("failed")
+    }
+
+    pub fn prune_max_entries() -> Arg<'static> {
+        Arg::new("max-entries")
+            .about("Keep at most the newest N entries [default: max_entries]")
+            .long("max-entries")
+            .value_name("N")
+            .takes_value(true)
+    }
+
+    pub fn prune_max_age() -> Arg<'static> {
+        Arg::new("max-age")
+            .about("Keep entries newer than AGE, e.g. 90d [default: max_age]")
+            .long("max-age")
+            .value_name("AGE")
+            .takes_value(true)
+    }
+
+    pub fn history_grep() -> Arg<'static> {
+        Arg::new("grep")
+            .about("Only history mentioning TEXT, ignoring case")
+            .long("grep")
+            .value_name("TEXT")
+            .takes_value(true)
     }
 }
@@ -0,0 +1,207 @@
+use serde::Deserialize;
+
+use std::error::Error;
+
+/// A command in a `toml` file, written either as a single string that gets
+/// split into arguments the way a POSIX shell would, or as an array of
+/// arguments used as-is.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl CommandLine {
+    pub fn args(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        match self {
+            CommandLine::Line(line) => split(line),
+            CommandLine::Args(args) => Ok(args.clone()),
+        }
+    }
+
+    /// Whether this is the single word `word`, as used by sentinel values
+    /// like `run = "BUILD"`.
+    pub fn is(&self, word: &str) -> bool {
+        match self {
+            CommandLine::Line(line) => line.trim() == word,
+            CommandLine::Args(args) => args.len() == 1 && args[0] == word,
+        }
+    }
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        CommandLine::Args(Vec::new())
+    }
+}
+
+/// Splits `line` into arguments following the POSIX shell's quoting rules:
+/// whitespace separates arguments, single quotes keep everything inside them
+/// as-is, double quotes keep everything but `\` escapes of `"`, `\`, `$` and
+/// `` ` ``, and a `\` outside of quotes escapes the next character. No
+/// variables, globs or other expansions are done.
+pub fn split(line: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // Distinguishes `''` (one empty argument) from no argument at all.
+    let mut in_arg = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated(line, '\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$')
+                            | Some(c @ '`') => arg.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(unterminated(line, '"')),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated(line, '"')),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                match chars.next() {
+                    Some('\n') => {}
+                    Some(c) => arg.push(c),
+                    None => {
+                        return Err(format!(
+                            "Trailing `\\` in command `{}`",
+                            line
+                        )
+                        .into())
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(arg.clone());
+                    arg.clear();
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+/// Joins `args` into a line `split` turns back into the same arguments,
+/// single-quoting the ones a shell would otherwise change.
+pub fn join(args: &[String]) -> String {
+    args.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" ")
+}
+
+/// `arg`, single-quoted if a shell would otherwise change it.
+pub fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn unterminated(line: &str, quote: char) -> Box<dyn Error> {
+    format!("Unterminated {} quote in command `{}`", quote, line).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_like_a_shell() -> Result<(), Box<dyn Error>> {
+        assert_eq!(split("g++  -Wall\t./src/main.cpp ")?, [
+            "g++",
+            "-Wall",
+            "./src/main.cpp",
+        ]);
+        assert_eq!(split(r#"cc -DNAME="a b" 'it''s' my\ file.c"#)?, [
+            "cc",
+            "-DNAME=a b",
+            "its",
+            "my file.c",
+        ]);
+        assert_eq!(split(r#"echo "\"q\" \$HOME \n" '\n' """#)?, [
+            "echo",
+            r#""q" $HOME \n"#,
+            r"\n",
+            "",
+        ]);
+        assert_eq!(split("ls")?, ["ls"]);
+        assert!(split("")?.is_empty());
+
+        assert!(split("echo 'oops").is_err());
+        assert!(split("echo \"oops").is_err());
+        assert!(split("echo oops\\").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn joined_lines_split_back_the_same() -> Result<(), Box<dyn Error>> {
+        let args: Vec<String> =
+            ["sh", "-c", "echo $HOME; ls", "it's", "", "./bin/debug/main"]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+
+        assert_eq!(
+            join(&args),
+            r#"sh -c 'echo $HOME; ls' 'it'\''s' '' ./bin/debug/main"#
+        );
+        assert_eq!(split(&join(&args))?, args);
+
+        Ok(())
+    }
+
+    #[test]
+    fn command_lines_parsed_from_strings_or_arrays(
+    ) -> Result<(), Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Cmds {
+            line: CommandLine,
+            args: CommandLine,
+        }
+
+        let cmds: Cmds = toml::from_str(
+            "line = \"ls -l 'My Documents'\"\n\
+            args = [\"ls\", \"-l\", \"My Documents\"]\n",
+        )?;
+        assert_eq!(cmds.line.args()?, cmds.args.args()?);
+        assert!(CommandLine::Line(" BUILD ".to_string()).is("BUILD"));
+        assert!(!cmds.line.is("ls"));
+
+        Ok(())
+    }
+}
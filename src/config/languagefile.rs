@@ -1,8 +1,17 @@
+use crate::config::command::CommandLine;
+use crate::config::spaceworkfile::SpaceworkFile;
+use crate::config::vars::Vars;
+use crate::spacework::{build, workspace};
+
+use glob::Pattern;
 use serde::Deserialize;
 
 use std::collections::HashMap;
+use std::env;
 use std::error::Error;
-use std::process::{Command, Output};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
 use std::str;
 
 #[derive(Debug, Deserialize)]
@@ -10,6 +19,12 @@ pub struct LanguageFile {
     pub language: Language,
     pub cmd: Cmd,
     pub workspace: Workspace,
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+
+    /// Where the language file was loaded from. `None` for built-ins.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,20 +38,43 @@ pub struct Language {
     pub extensions: Vec<String>,
     pub optimization_levels: Vec<String>,
     pub warnings: Vec<String>,
+
+    /// Flag for compiling a source into an object file without linking,
+    /// e.g. `-c`. Builds are incremental when this is set.
+    pub compile_only: Option<String>,
+    /// Flags making the compiler write a Makefile-style `.d` file next to
+    /// each object, listing the headers it depends on, e.g. `-MMD`.
+    #[serde(default)]
+    pub depfile: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Workspace {
     pub dir: String,
     pub src: String,
+    pub template: Option<String>,
+}
+
+/// Named set of build flags, e.g. `debug` or `release`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Profile {
+    /// Each must be one of the language's `optimization_levels`.
+    #[serde(default)]
+    pub optimization: Vec<String>,
+    #[serde(default)]
+    pub flags: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Cmd {
-    pub build: String,
-    pub run: String,
+    /// Used as-is when present, instead of putting a command together from
+    /// the `[language]` fields.
+    pub build: Option<CommandLine>,
+    pub run: CommandLine,
 }
 
+pub const DEFAULT_PROFILE: &str = "debug";
+
 const LANGFILES: [&str; 2] = [
     include_str!("../../langs/c.toml"),
     include_str!("../../langs/cpp.toml"),
@@ -54,12 +92,18 @@ const TEMPLATE_SRC: [&str; 2] = [
 
 impl LanguageFile {
     pub fn from_language(lang_name: &str) -> Result<Self, Box<dyn Error>> {
-        let lang_name = lang_name.to_lowercase();
-        let langfiles: Vec<LanguageFile> = Self::langfiles()?;
+        Self::from_language_in(lang_name, &Self::user_langfile_dirs())
+    }
+
+    /// Like `from_language`, but with the language files in `dirs` instead
+    /// of the user's, see `langfiles_from`.
+    fn from_language_in(
+        lang_name: &str,
+        dirs: &[PathBuf],
+    ) -> Result<Self, Box<dyn Error>> {
+        let langfiles: Vec<LanguageFile> = Self::langfiles_from(dirs)?;
         for langfile in langfiles {
-            if langfile.language.aliases.contains(&lang_name)
-                || langfile.language.name == lang_name
-            {
+            if langfile.is_called(lang_name) {
                 return Ok(langfile);
             }
         }
@@ -73,22 +117,235 @@ impl LanguageFile {
         )
     }
 
-    pub fn template(&self) -> Result<&'static str, Box<dyn Error>> {
+    /// Whether `name` is this language's name or one of its aliases.
+    pub fn is_called(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.language.name.to_lowercase() == name
+            || self
+                .language
+                .aliases
+                .iter()
+                .any(|alias| alias.to_lowercase() == name)
+    }
+
+    pub fn template(&self) -> Result<String, Box<dyn Error>> {
+        let template = self
+            .workspace
+            .template
+            .as_ref()
+            .unwrap_or(&self.workspace.src);
+
+        // User language files keep their templates next to them, in
+        // `templates/`, the same way the built-in ones do.
+        if let Some(dir) = self.path.as_ref().and_then(|p| p.parent()) {
+            let user_template = dir.join("templates").join(template);
+            if user_template.is_file() {
+                return Ok(fs::read_to_string(user_template)?);
+            }
+        }
+
         let templates: HashMap<String, &str> = TEMPLATE_SRC
             .iter()
             .map(|e| e.to_string())
             .zip(TEMPLATES)
             .collect();
 
-        if let Some(v) = templates.get(&self.workspace.src) {
-            Ok(v)
+        if let Some(v) = templates.get(template) {
+            Ok(v.to_string())
         } else {
-            Err("Unable to find matching template file.".into())
+            Err(format!(
+                "Unable to find matching template file `{}`.",
+                template
+            )
+            .into())
+        }
+    }
+
+
+    /// Builds the project, returning the output of the commands that were
+    /// run, or `None` if everything was already up to date.
+    pub fn build(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &str,
+    ) -> Result<Option<Output>, Box<dyn Error>> {
+        // Checked before making `bin/<profile>`, so a name like `../x`
+        // can't make directories outside the project.
+        self.profile(cfg, profile)?;
+        fs::create_dir_all(cfg.root.join("bin").join(profile))?;
+
+        if self.cmd.build.is_none() && self.language.compile_only.is_some() {
+            return build::incremental(self, cfg, profile);
         }
+
+        Ok(Some(build::run(&self.build_command(cfg, profile)?, &cfg.root)?))
     }
 
+    /// The build command's arguments, starting with the program to run.
+    pub fn build_command(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        match &self.cmd.build {
+            Some(build) => {
+                Vars::project(cfg, self, profile)?.expand(&build.args()?)
+            }
+            None => {
+                let profile_flags = self.profile(cfg, profile)?;
+                let mut args = self.compile_flags(cfg, &profile_flags);
+                args.extend(self.sources(cfg)?.iter().map(|src| dotted(src)));
+                args.push(self.language.output.clone());
+                args.push(dotted(&self.binary(profile)?));
+
+                Ok(args)
+            }
+        }
+    }
 
-    pub fn build(&self) -> Result<Output, Box<dyn Error>> {
+    /// Command compiling `src` into the object file `obj`, both relative to
+    /// the project root.
+    pub fn compile_command(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &Profile,
+        src: &Path,
+        obj: &Path,
+    ) -> Vec<String> {
+        let mut args = self.compile_flags(cfg, profile);
+        args.extend(self.language.depfile.iter().cloned());
+        args.extend(self.language.compile_only.iter().cloned());
+        args.push(dotted(src));
+        args.push(self.language.output.clone());
+        args.push(dotted(obj));
+
+        args
+    }
+
+    /// Command linking `objects` into the binary `bin`, all relative to the
+    /// project root.
+    pub fn link_command(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &Profile,
+        objects: &[PathBuf],
+        bin: &Path,
+    ) -> Vec<String> {
+        let mut args = vec![self.compiler(cfg).to_string()];
+        args.extend(cfg.build.flags.iter().cloned());
+        args.extend(profile.optimization.iter().cloned());
+        args.extend(profile.flags.iter().cloned());
+        args.extend(objects.iter().map(|obj| dotted(obj)));
+        args.push(self.language.output.clone());
+        args.push(dotted(bin));
+
+        args
+    }
+
+    /// Where `profile`'s binary goes, relative to the project root.
+    pub fn binary(&self, profile: &str) -> Result<PathBuf, Box<dyn Error>> {
+        check_profile_name(profile)?;
+        Ok(Path::new("bin").join(profile).join(self.outfile()?))
+    }
+
+    /// Every file under the project's `src/` directory with one of the
+    /// language's extensions, filtered by the `[build]` table's `include`
+    /// and `exclude` globs. Paths are relative to the project root.
+    pub fn sources(
+        &self,
+        cfg: &SpaceworkFile,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let include = patterns(&cfg.build.include)?;
+        let exclude = patterns(&cfg.build.exclude)?;
+
+        let mut files = Vec::new();
+        find_files(&cfg.root.join("src"), &mut files)?;
+
+        let mut sources: Vec<PathBuf> = files
+            .into_iter()
+            .filter_map(|file| {
+                file.strip_prefix(&cfg.root).ok().map(|f| f.to_path_buf())
+            })
+            .filter(|file| {
+                file.extension().is_some_and(|ext| {
+                    self.language.extensions.iter().any(|e| ext == e.as_str())
+                })
+            })
+            .filter(|file| {
+                include.is_empty()
+                    || include.iter().any(|p| p.matches_path(file))
+            })
+            .filter(|file| !exclude.iter().any(|p| p.matches_path(file)))
+            .collect();
+        sources.sort();
+
+        if sources.is_empty() {
+            return Err(format!(
+                "No {} source files found in {}",
+                self.language.name,
+                cfg.root.join("src").display()
+            )
+            .into());
+        }
+
+        Ok(sources)
+    }
+
+    /// Whether `spacework run` should build the project first.
+    pub fn builds_before_run(&self) -> bool {
+        self.cmd.run.is("BUILD")
+    }
+
+    /// Runs the project from its root directory, with `args` added to the
+    /// end of the command and the terminal's stdin, stdout and stderr.
+    pub fn run(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &str,
+        args: &[String],
+    ) -> Result<ExitStatus, Box<dyn Error>> {
+        let run = self.run_command(cfg, profile)?;
+        let (bin, run) = match run.split_first() {
+            Some((bin, run)) => (bin, run),
+            _ => return Err("Run command is empty".into()),
+        };
+
+        match Command::new(bin)
+            .args(run)
+            .args(args)
+            .current_dir(&cfg.root)
+            .status()
+        {
+            Ok(status) => Ok(status),
+            Err(e) => Err(format!("Unable to run `{}`: {}", bin, e).into()),
+        }
+    }
+
+    /// `BUILD` and `PASS` run the profile's binary, with and without building
+    /// it first. Any other `cmd.run` is the command to run instead.
+    pub fn run_command(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.cmd.run.is("BUILD") || self.cmd.run.is("PASS") {
+            let bin = cfg.root.join(self.binary(profile)?);
+            if !bin.is_file() {
+                return Err(format!(
+                    "`{}` not found. Has the project been built?",
+                    bin.display()
+                )
+                .into());
+            }
+
+            Ok(vec![bin.to_string_lossy().into_owned()])
+        } else {
+            Vars::project(cfg, self, profile)?.expand(&self.cmd.run.args()?)
+        }
+    }
+
+    /// `workspace.src` without its extension.
+    pub fn outfile(&self) -> Result<String, Box<dyn Error>> {
         let mut outfile = self.workspace.src.clone();
 
         for ext in self.language.extensions.iter() {
@@ -107,25 +364,82 @@ impl LanguageFile {
             .into());
         }
 
-        // TODO:
-        // Make a list of these variables.
-        // `SRC`, `OUT`, etc.
-        let on_build = self
-            .cmd
-            .build
-            .replace("SRC", &self.workspace.src)
-            .replace("OUT", &outfile);
-        let (bin, args) = match on_build.split_once(' ') {
-            Some((bin, args)) => (bin, args),
-            _ => return Err("Problem parsing arguments".into()),
+        Ok(outfile)
+    }
+
+    /// Looks `name` up in the project's profiles, then the language's.
+    /// `debug` and `release` always exist, even if neither defines them.
+    pub fn profile(
+        &self,
+        cfg: &SpaceworkFile,
+        name: &str,
+    ) -> Result<Profile, Box<dyn Error>> {
+        check_profile_name(name)?;
+
+        let profile = match cfg.profile.get(name).or(self.profile.get(name)) {
+            Some(profile) => profile.clone(),
+            None if name == "debug" || name == "release" => Profile::default(),
+            None => {
+                return Err(format!("Profile `{}` not found", name).into())
+            }
         };
 
-        let cmd = Command::new(bin).args(args.split_whitespace()).output()?;
+        for level in profile.optimization.iter() {
+            if !self.language.optimization_levels.contains(level) {
+                return Err(format!(
+                    "`{}` in profile `{}` is not one of {}'s optimization \
+                    levels: {}",
+                    level,
+                    name,
+                    self.language.name,
+                    self.language.optimization_levels.join(", ")
+                )
+                .into());
+            }
+        }
+
+        Ok(profile)
+    }
+
+    fn compiler<'a>(&'a self, cfg: &'a SpaceworkFile) -> &'a str {
+        cfg.build.compiler.as_ref().unwrap_or(&self.language.compiler)
+    }
+
+    /// `compiler standard warnings... flags...` from the `[language]` table,
+    /// the project's `[build]` table and the selected profile.
+    fn compile_flags(
+        &self,
+        cfg: &SpaceworkFile,
+        profile: &Profile,
+    ) -> Vec<String> {
+        let overrides = &cfg.build;
 
-        Ok(cmd)
+        let mut args = vec![self.compiler(cfg).to_string()];
+        if let Some(standard) =
+            overrides.standard.as_ref().or(self.language.standard.as_ref())
+        {
+            args.push(standard.clone());
+        }
+        args.extend(self.language.warnings.iter().cloned());
+        args.extend(overrides.warnings.iter().cloned());
+        args.extend(overrides.flags.iter().cloned());
+        args.extend(profile.optimization.iter().cloned());
+        args.extend(profile.flags.iter().cloned());
+
+        args
     }
 
+    /// Built-in language files, followed by any found in
+    /// `~/spacework/langs/` and then the current project's `langs/`.
     fn langfiles() -> Result<Vec<LanguageFile>, Box<dyn Error>> {
+        Self::langfiles_from(&Self::user_langfile_dirs())
+    }
+
+    /// Built-in language files, followed by any found in each of `dirs` in
+    /// turn. Later files replace earlier ones sharing a name or alias.
+    fn langfiles_from(
+        dirs: &[PathBuf],
+    ) -> Result<Vec<LanguageFile>, Box<dyn Error>> {
 /*
         // I have absolutely zero idea how to do this without
         // calling `unwrap` directly :c
@@ -138,9 +452,81 @@ impl LanguageFile {
         for langfile in LANGFILES.iter() {
             langfiles.push(toml::from_str(langfile)?);
         }
+
+        for dir in dirs {
+            for langfile in Self::langfiles_in_dir(dir)? {
+                Self::merge(&mut langfiles, langfile);
+            }
+        }
+
         Ok(langfiles)
     }
 
+    fn user_langfile_dirs() -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Ok(workspace_root) = workspace::workspace_dir() {
+            dirs.push(workspace_root.join("langs"));
+        }
+
+        // Not being inside a project is fine, e.g. for `spacework new`.
+        if let Ok(mut dir) = env::current_dir() {
+            if let Ok(cfg) = SpaceworkFile::find_in_dir(&mut dir) {
+                dirs.push(cfg.root.join("langs"));
+            }
+        }
+
+        dirs
+    }
+
+    fn langfiles_in_dir(
+        dir: &Path,
+    ) -> Result<Vec<LanguageFile>, Box<dyn Error>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path.extension().is_some_and(|ext| ext == "toml")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut langfiles = Vec::new();
+        for path in paths {
+            let mut langfile: LanguageFile =
+                match toml::from_str(&fs::read_to_string(&path)?) {
+                    Ok(langfile) => langfile,
+                    Err(e) => return Err(format!(
+                        "Unable to parse language file {}: {}",
+                        path.display(),
+                        e
+                    )
+                    .into()),
+                };
+            langfile.path = Some(path);
+            langfiles.push(langfile);
+        }
+
+        Ok(langfiles)
+    }
+
+    fn merge(langfiles: &mut Vec<LanguageFile>, langfile: LanguageFile) {
+        langfiles.retain(|existing| {
+            !langfile.is_called(&existing.language.name)
+                && !existing
+                    .language
+                    .aliases
+                    .iter()
+                    .any(|alias| langfile.is_called(alias))
+        });
+        langfiles.push(langfile);
+    }
+
     // TODO:
     // Do something with this D:
     #[allow(dead_code)]
@@ -160,14 +546,85 @@ impl LanguageFile {
     }
 }
 
+/// `path` as a `./`-prefixed string, the way paths are written in commands.
+fn dotted(path: &Path) -> String {
+    format!("./{}", path.display())
+}
+
+fn patterns(globs: &[String]) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    let mut patterns = Vec::new();
+    for glob in globs {
+        match Pattern::new(glob) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => {
+                return Err(
+                    format!("Invalid glob pattern `{}`: {}", glob, e).into()
+                )
+            }
+        }
+    }
+
+    Ok(patterns)
+}
+
+fn find_files(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Profile names are used as directory names, so they can only have
+/// letters, digits, `-` and `_`.
+fn check_profile_name(name: &str) -> Result<(), Box<dyn Error>> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid profile name `{}`", name).into());
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
     use std::env;
     use std::error::Error;
     use std::fs;
     use std::path::Path;
 
+    /// Makes a throwaway project directory containing empty `files`.
+    fn temp_project(
+        name: &str,
+        files: &[&str],
+    ) -> Result<TempDir, Box<dyn Error>> {
+        let root = TempDir::new(name)?;
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, "")?;
+        }
+
+        Ok(root)
+    }
+
     #[test]
     fn example_langfile_found_and_parseable() -> Result<(), Box<dyn Error>> {
         let langfile = Path::new(&env::var("CARGO_MANIFEST_DIR")?)
@@ -196,7 +653,198 @@ mod tests {
                     )?;
                 }
         */
-        LanguageFile::langfiles()?;
+        LanguageFile::langfiles_from(&[])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_command_composed_from_language_fields(
+    ) -> Result<(), Box<dyn Error>> {
+        let cpp = LanguageFile::from_language_in("cpp", &[])?;
+        let root = temp_project("build", &["src/main.cpp"])?;
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n",
+        )?;
+        cfg.root = root.to_path_buf();
+        assert_eq!(
+            cpp.build_command(&cfg, DEFAULT_PROFILE)?,
+            [
+                "g++", "-std=c++20", "-Wall", "-Wextra", "-Wpedantic",
+                "-g", "-O0", "./src/main.cpp", "-o", "./bin/debug/main",
+            ]
+        );
+
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n\
+            [build]\nstandard = \"-std=c++17\"\nwarnings = [\"-Werror\"]\n\
+            flags = [\"-DNDEBUG\"]\n",
+        )?;
+        cfg.root = root.to_path_buf();
+        assert_eq!(
+            cpp.build_command(&cfg, "release")?,
+            [
+                "g++", "-std=c++17", "-Wall", "-Wextra", "-Wpedantic",
+                "-Werror", "-DNDEBUG", "-O3", "./src/main.cpp", "-o",
+                "./bin/release/main",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sources_found_by_extension_and_filtered_by_globs(
+    ) -> Result<(), Box<dyn Error>> {
+        let cpp = LanguageFile::from_language_in("cpp", &[])?;
+        let root = temp_project(
+            "sources",
+            &[
+                "src/main.cpp",
+                "src/util/math.cc",
+                "src/util/math.hpp",
+                "src/scratch/old.cpp",
+                "src/notes.txt",
+            ],
+        )?;
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n",
+        )?;
+        cfg.root = root.to_path_buf();
+        assert_eq!(
+            cpp.sources(&cfg)?,
+            [
+                Path::new("src/main.cpp"),
+                Path::new("src/scratch/old.cpp"),
+                Path::new("src/util/math.cc"),
+            ]
+        );
+
+        cfg.build.exclude = vec!["src/scratch/**".to_string()];
+        assert_eq!(
+            cpp.build_command(&cfg, DEFAULT_PROFILE)?[7..9],
+            ["./src/main.cpp", "./src/util/math.cc"]
+        );
+
+        cfg.build.include = vec!["src/util/*".to_string()];
+        assert_eq!(cpp.sources(&cfg)?, [Path::new("src/util/math.cc")]);
+
+        cfg.build.include = vec!["src/nothing/*".to_string()];
+        assert!(cpp.sources(&cfg).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_command_uses_binary_or_custom_command(
+    ) -> Result<(), Box<dyn Error>> {
+        let mut cpp = LanguageFile::from_language_in("cpp", &[])?;
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n",
+        )?;
+        let root = TempDir::new("run")?;
+        cfg.root = root.join("hello");
+
+        assert!(cpp.builds_before_run());
+        assert!(cpp.run_command(&cfg, "release").is_err());
+
+        fs::create_dir_all(cfg.root.join("bin/release"))?;
+        fs::write(cfg.root.join("bin/release/main"), "")?;
+        assert_eq!(
+            cpp.run_command(&cfg, "release")?,
+            [cfg.root.join("bin/release/main").to_string_lossy()]
+        );
+
+        cpp.cmd.run =
+            CommandLine::Line("valgrind ${bin_dir}/${out}".to_string());
+        assert!(!cpp.builds_before_run());
+        assert_eq!(
+            cpp.run_command(&cfg, "debug")?,
+            [
+                "valgrind".to_string(),
+                cfg.root.join("bin/debug/main").to_string_lossy().into(),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn project_profiles_override_language_profiles(
+    ) -> Result<(), Box<dyn Error>> {
+        let cpp = LanguageFile::from_language_in("cpp", &[])?;
+        let cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n\
+            [profile.release]\noptimization = [\"-O2\"]\n\
+            [profile.asan]\noptimization = [\"-g\"]\n\
+            flags = [\"-fsanitize=address\"]\n\
+            [profile.broken]\noptimization = [\"-O9\"]\n",
+        )?;
+
+        assert_eq!(cpp.profile(&cfg, "release")?.optimization, ["-O2"]);
+        assert_eq!(cpp.profile(&cfg, "debug")?.optimization, ["-g", "-O0"]);
+        assert_eq!(cpp.profile(&cfg, "asan")?.flags, ["-fsanitize=address"]);
+        assert!(cpp.profile(&cfg, "broken").is_err());
+        assert!(cpp.profile(&cfg, "missing").is_err());
+        assert!(cpp.profile(&cfg, "../release").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_profiles_rejected_before_building() -> Result<(), Box<dyn Error>> {
+        let cpp = LanguageFile::from_language_in("cpp", &[])?;
+        let root = temp_project("bad_profile", &["src/main.cpp"])?;
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n",
+        )?;
+        cfg.root = root.join("project");
+
+        let escaped = cpp.build(&cfg, "../../escaped");
+        let missing = cpp.build(&cfg, "missing");
+        let made = (root.join("escaped").exists(), cfg.root.exists());
+
+        assert!(escaped.is_err());
+        assert!(missing.is_err());
+        assert!(cpp.binary("../x").is_err());
+        assert_eq!(made, (false, false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_langfiles_extend_and_override_builtins(
+    ) -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("langs")?;
+        fs::create_dir_all(dir.join("templates"))?;
+        fs::write(
+            dir.join("fortran.toml"),
+            "[language]\nname = \"Fortran\"\naliases = [\"f90\"]\n\
+            compiler = \"gfortran\"\nextensions = [\"f90\"]\n\
+            warnings = []\noutput = \"-o\"\noptimization_levels = []\n\
+            [workspace]\ndir = \"fortran\"\nsrc = \"main.f90\"\n\
+            [cmd]\nbuild = \"gfortran ${sources} -o ${bin_dir}/${out}\"\n\
+            run = \"BUILD\"\n",
+        )?;
+        fs::write(dir.join("templates/main.f90"), "end program\n")?;
+        fs::write(
+            dir.join("clang.toml"),
+            "[language]\nname = \"clang++\"\naliases = [\"cpp\"]\n\
+            compiler = \"clang++\"\nextensions = [\"cpp\"]\n\
+            warnings = []\noutput = \"-o\"\noptimization_levels = []\n\
+            [workspace]\ndir = \"cpp\"\nsrc = \"main.cpp\"\n\
+            [cmd]\nbuild = \"clang++ ${sources} -o ${bin_dir}/${out}\"\n\
+            run = \"BUILD\"\n",
+        )?;
+
+        let langfiles = LanguageFile::langfiles_from(&[dir.to_path_buf()])?;
+        let find = |name: &str| langfiles.iter().find(|lf| lf.is_called(name));
+
+        let fortran = find("F90").expect("user language file not loaded");
+        assert_eq!(fortran.template()?, "end program\n");
+        assert_eq!(find("cpp").unwrap().language.compiler, "clang++");
+        assert!(find("cxx").is_none());
+        assert!(find("c").is_some());
 
         Ok(())
     }
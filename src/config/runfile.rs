@@ -1,43 +1,699 @@
+use crate::config::command::{self, CommandLine};
+use crate::config::languagefile::{LanguageFile, DEFAULT_PROFILE};
+use crate::config::spaceworkfile::SpaceworkFile;
+use crate::config::vars::Vars;
+use crate::spacework::{process, workspace};
+
 use serde::Deserialize;
 
+use std::collections::{BTreeMap, HashSet};
+use std::env;
 use std::error::Error;
-use std::process::{Command, Output};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize)]
 struct RunFile {
+    #[serde(default)]
     cmd: Vec<Cmd>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Cmd {
-    name: String,
-    bin: String,
-    args: String,
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Cmd {
+    pub name: String,
+    #[serde(default)]
+    pub bin: String,
+    #[serde(default)]
+    pub args: CommandLine,
+    /// Run `bin` and `args` with `sh -c`, so they can use pipes,
+    /// redirection and the like.
+    #[serde(default)]
+    pub shell: bool,
+    /// A shell script to run with `sh -c` instead of `bin` and `args`.
+    pub script: Option<String>,
+    /// What the command is for, shown by `spacework commands`.
+    pub description: Option<String>,
+
+    /// Commands to run before this one. `build` means `spacework build`.
+    #[serde(default)]
+    pub depends: Vec<String>,
+
+    /// Environment variables to set for the command, on top of ours.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Directory to run the command in, relative to the project root.
+    /// Defaults to the project root, or the current directory outside of a
+    /// project.
+    pub cwd: Option<String>,
+    /// Seconds the command may run for before it's killed, along with
+    /// everything it started.
+    pub timeout: Option<u64>,
+}
+
+impl Cmd {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    /// The script to give `sh -c`, if this is a shell command.
+    fn script(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match (&self.script, self.bin.is_empty()) {
+            (Some(_), false) => Err(format!(
+                "`{}` has both a `bin` and a `script`, only one can be used",
+                self.name
+            )
+            .into()),
+            (Some(script), true) => Ok(Some(script.clone())),
+            (None, true) => Err(format!(
+                "`{}` needs either a `bin` or a `script`",
+                self.name
+            )
+            .into()),
+            (None, false) if self.shell => Ok(Some(match &self.args {
+                CommandLine::Line(line) => format!("{} {}", self.bin, line),
+                CommandLine::Args(args) if args.is_empty() => self.bin.clone(),
+                CommandLine::Args(args) => {
+                    // Arguments with variables in are left for the shell to
+                    // replace, and split up if they're lists.
+                    let args: Vec<_> = args
+                        .iter()
+                        .map(|arg| match arg.contains('$') {
+                            true => arg.clone(),
+                            false => command::quote(arg),
+                        })
+                        .collect();
+                    format!("{} {}", self.bin, args.join(" "))
+                }
+            })),
+            (None, false) => Ok(None),
+        }
+    }
+
+    /// Whether `${args}` appears in the command.
+    fn uses_args(&self) -> Result<bool, Box<dyn Error>> {
+        let mut args = vec![self.bin.clone()];
+        args.extend(self.args.args()?);
+
+        Vars::uses(&args, "args")
+    }
+}
+
+/// One of the commands `spacework <command>` ends up running.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// `spacework build` with the default profile.
+    Build,
+    Cmd(Box<Cmd>),
+}
+
+impl Step {
+    pub fn name(&self) -> &str {
+        match self {
+            Step::Build => "build",
+            Step::Cmd(cmd) => &cmd.name,
+        }
+    }
+}
+
+/// Where a runfile command was defined. Project commands shadow global ones
+/// with the same name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    /// Comes with spacework.
+    BuiltIn,
+    /// `~/spacework/runfile.toml`
+    Global,
+    /// The project's `runfile.toml`, or `[[cmd]]` tables in its
+    /// `spacework.toml`, which take priority.
+    Project,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::BuiltIn => write!(f, "built-in"),
+            Origin::Global => write!(f, "global"),
+            Origin::Project => write!(f, "project"),
+        }
+    }
+}
+
+const RUNFILE: &str = "runfile.toml";
+
+/// spacework's own subcommands, with what they do. `spacework <command>`
+/// would never get to run a runfile command named after one of them. Only
+/// `build` can be a runfile command's dependency.
+pub const BUILT_INS: &[(&str, &str)] = &[
+    ("new", "Create a new project"),
+    ("build", "Build the project with the default profile"),
+    ("run", "Build the project if needed, then run it"),
+    ("commands", "List the commands spacework <COMMAND> can run"),
+    ("resume", "Print the path of the project last worked on"),
+    ("history", "View previous spacework actions"),
+    ("help", "Print help for spacework or one of its subcommands"),
+];
+
+/// The commands called `names` and everything they depend on, in the order
+/// they need to run. Each command is only run once, even if several others
+/// depend on it.
+pub fn plan(names: &[&str]) -> Result<Vec<Step>, Box<dyn Error>> {
+    let project = current_project()?;
+
+    resolve(&commands(project.as_ref().map(|(cfg, _)| cfg))?, names)
+}
+
+/// Every runfile command `spacework <command>` accepts here, with where
+/// it's from. spacework's own are in `BUILT_INS`.
+pub fn available() -> Result<Vec<(Origin, Cmd)>, Box<dyn Error>> {
+    let project = current_project()?;
+
+    commands(project.as_ref().map(|(cfg, _)| cfg))
+}
+
+/// Runs `cmd` on its own, without its dependencies. See `command_line` for
+/// what happens to `extra_args`.
+pub fn run(
+    cmd: &Cmd,
+    extra_args: &[String],
+) -> Result<process::Finished, Box<dyn Error>> {
+    let project = current_project()?;
+    let vars = vars(project.as_ref(), extra_args)?;
+    let cfg = project.as_ref().map(|(cfg, _)| cfg);
+
+    run_in(cmd, cfg, &vars, extra_args)
+}
+
+/// Runs `cmd` in `project`, or outside of one, with `vars`.
+fn run_in(
+    cmd: &Cmd,
+    project: Option<&SpaceworkFile>,
+    vars: &Vars,
+    extra_args: &[String],
+) -> Result<process::Finished, Box<dyn Error>> {
+    let args = expand(cmd, vars, extra_args)?;
+    let (bin, args) = match args.split_first() {
+        Some((bin, args)) => (bin, args),
+        _ => return Err(format!("`{}` is empty", cmd.name).into()),
+    };
+
+    let mut command = Command::new(bin);
+    command.args(args);
+    if cmd.script()?.is_some() {
+        command.envs(vars.iter());
+    }
+    if let Some(dir) = working_dir(cmd, project, vars)? {
+        if !dir.is_dir() {
+            return Err(format!(
+                "Working directory of `{}` not found: {}",
+                cmd.name,
+                dir.display()
+            )
+            .into());
+        }
+        command.current_dir(dir);
+    }
+    for (name, value) in cmd.env.iter() {
+        command.env(name, vars.expand_one(value)?);
+    }
+
+    match process::run_with_timeout(&mut command, cmd.timeout()) {
+        Ok(finished) => Ok(finished),
+        Err(e) => Err(format!("Unable to run `{}`: {}", bin, e).into()),
+    }
+}
+
+/// `cmd`'s arguments with variables expanded, starting with the program.
+/// `extra_args` go wherever `${args}` is used, or at the end if it isn't.
+pub fn command_line(
+    cmd: &Cmd,
+    extra_args: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let vars = vars(current_project()?.as_ref(), extra_args)?;
+
+    expand(cmd, &vars, extra_args)
+}
+
+/// Shell commands aren't expanded, as their variables are in the
+/// environment instead, and `extra_args` become the script's `$1`, `$2`...
+fn expand(
+    cmd: &Cmd,
+    vars: &Vars,
+    extra_args: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(script) = cmd.script()? {
+        let mut args = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            script,
+            cmd.name.clone(),
+        ];
+        args.extend(extra_args.iter().cloned());
+
+        return Ok(args);
+    }
+
+    let mut args = vec![cmd.bin.clone()];
+    args.extend(cmd.args.args()?);
+
+    let mut args = vars.expand(&args)?;
+    if !cmd.uses_args()? {
+        args.extend(extra_args.iter().cloned());
+    }
+
+    Ok(args)
 }
 
-pub fn run(command: &str) -> Result<Output, Box<dyn Error>> {
-    let runfile: RunFile = toml::from_str(
-        include_str!("../../runfiles/example.toml")
-    )?;
+/// Where `cmd` runs, or `None` for the current directory.
+fn working_dir(
+    cmd: &Cmd,
+    project: Option<&SpaceworkFile>,
+    vars: &Vars,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let cwd = match &cmd.cwd {
+        Some(cwd) => PathBuf::from(vars.expand_one(cwd)?),
+        None => return Ok(project.map(|cfg| cfg.root.clone())),
+    };
 
-    match runfile.cmd.iter().find(|&c| c.name == command) {
-        Some(cmd) => Ok(Command::new(&cmd.bin)
-            .args(cmd.args.split_whitespace())
-            .output()?),
-        None => Err(format!("`{}` not found in runfile", command).into()),
+    match project {
+        Some(cfg) => Ok(Some(cfg.root.join(cwd))),
+        None => Ok(Some(cwd)),
     }
 }
 
-// TODO:
-// Make tests D:
-/*
+fn resolve(
+    cmds: &[(Origin, Cmd)],
+    names: &[&str],
+) -> Result<Vec<Step>, Box<dyn Error>> {
+    let mut steps = Vec::new();
+    let mut done = HashSet::new();
+    for name in names {
+        visit(cmds, name, &mut Vec::new(), &mut done, &mut steps)?;
+    }
+
+    Ok(steps)
+}
+
+/// Depth-first walk through `name`'s dependencies. `path` is the chain of
+/// commands that led here, for spotting cycles.
+fn visit(
+    cmds: &[(Origin, Cmd)],
+    name: &str,
+    path: &mut Vec<String>,
+    done: &mut HashSet<String>,
+    steps: &mut Vec<Step>,
+) -> Result<(), Box<dyn Error>> {
+    if done.contains(name) {
+        return Ok(());
+    }
+    if path.iter().any(|n| n == name) {
+        path.push(name.to_string());
+        return Err(
+            format!("Dependency cycle in runfiles: {}", path.join(" -> "))
+                .into(),
+        );
+    }
+
+    let step = match cmds.iter().find(|(_, c)| c.name == name) {
+        Some((_, cmd)) => Step::Cmd(Box::new(cmd.clone())),
+        None if name == "build" => Step::Build,
+        None => match path.last() {
+            Some(parent) => {
+                return Err(format!(
+                    "`{}` depends on `{}`, which isn't in any runfile",
+                    parent, name
+                )
+                .into())
+            }
+            None => {
+                return Err(format!("`{}` not found in runfiles", name).into())
+            }
+        },
+    };
+
+    if let Step::Cmd(cmd) = &step {
+        path.push(name.to_string());
+        for dependency in cmd.depends.iter() {
+            visit(cmds, dependency, path, done, steps)?;
+        }
+        path.pop();
+    }
+
+    done.insert(name.to_string());
+    steps.push(step);
+
+    Ok(())
+}
+
+/// Every command from `~/spacework/runfile.toml` and, inside a project, its
+/// runfiles, with shadowed commands left out.
+pub fn commands(
+    project: Option<&SpaceworkFile>,
+) -> Result<Vec<(Origin, Cmd)>, Box<dyn Error>> {
+    commands_in(workspace::workspace_dir().ok().as_deref(), project)
+}
+
+/// `commands`, with the global runfile in `workspace_root`.
+fn commands_in(
+    workspace_root: Option<&Path>,
+    project: Option<&SpaceworkFile>,
+) -> Result<Vec<(Origin, Cmd)>, Box<dyn Error>> {
+    let mut cmds = Vec::new();
+    if let Some(workspace_root) = workspace_root {
+        for cmd in read_runfile(&workspace_root.join(RUNFILE))? {
+            add(&mut cmds, Origin::Global, cmd);
+        }
+    }
+
+    if let Some(cfg) = project {
+        for cmd in read_runfile(&cfg.root.join(RUNFILE))? {
+            add(&mut cmds, Origin::Project, cmd);
+        }
+        check_names(&cfg.cmd, &cfg.root.join("spacework.toml"))?;
+        for cmd in cfg.cmd.iter() {
+            add(&mut cmds, Origin::Project, cmd.clone());
+        }
+    }
+
+    Ok(cmds)
+}
+
+fn add(cmds: &mut Vec<(Origin, Cmd)>, origin: Origin, cmd: Cmd) {
+    cmds.retain(|(_, c)| c.name != cmd.name);
+    cmds.push((origin, cmd));
+}
+
+/// Commands in the runfile at `path`, or none if it doesn't exist.
+fn read_runfile(path: &Path) -> Result<Vec<Cmd>, Box<dyn Error>> {
+    let runfile = match fs::read_to_string(path) {
+        Ok(runfile) => runfile,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(format!(
+                "Unable to read runfile {}: {}",
+                path.display(),
+                e
+            )
+            .into())
+        }
+    };
+
+    let cmds = match toml::from_str::<RunFile>(&runfile) {
+        Ok(runfile) => runfile.cmd,
+        Err(e) => {
+            return Err(format!(
+                "Unable to parse runfile {}: {}",
+                path.display(),
+                e
+            )
+            .into())
+        }
+    };
+    check_names(&cmds, path)?;
+
+    Ok(cmds)
+}
+
+/// Makes sure none of `cmds`, from the file at `path`, are named after one
+/// of spacework's subcommands.
+fn check_names(cmds: &[Cmd], path: &Path) -> Result<(), Box<dyn Error>> {
+    let reserved: Vec<&str> = BUILT_INS.iter().map(|(name, _)| *name).collect();
+    match cmds.iter().find(|cmd| reserved.contains(&cmd.name.as_str())) {
+        Some(cmd) => Err(format!(
+            "`{}` in {} has the same name as a spacework subcommand, so it \
+            could never run. Reserved names: {}",
+            cmd.name,
+            path.display(),
+            reserved.join(", ")
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// The project containing the current directory, if there is one. A
+/// project whose files are broken is an error, not `None`.
+fn current_project(
+) -> Result<Option<(SpaceworkFile, LanguageFile)>, Box<dyn Error>> {
+    let cfg = match SpaceworkFile::find(&env::current_dir()?)? {
+        Some(cfg) => cfg,
+        None => return Ok(None),
+    };
+    let langfile = LanguageFile::from_language(&cfg.workspace.language)?;
+
+    Ok(Some((cfg, langfile)))
+}
+
+/// The project's variables, or only `${env:NAME}` outside of one, with
+/// `${args}` set to `extra_args`.
+fn vars(
+    project: Option<&(SpaceworkFile, LanguageFile)>,
+    extra_args: &[String],
+) -> Result<Vars, Box<dyn Error>> {
+    let mut vars = match project {
+        Some((cfg, langfile)) => Vars::project(cfg, langfile, DEFAULT_PROFILE)?,
+        None => Vars::new(),
+    };
+    vars.set_many("args", extra_args.to_vec());
+
+    Ok(vars)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
 
     #[test]
-    fn floops_n_bloops() {
-        assert!(true);
+    fn example_runfile_parseable() -> Result<(), Box<dyn Error>> {
+        let runfile: RunFile =
+            toml::from_str(include_str!("../../runfiles/example.toml"))?;
+        assert!(runfile.cmd.iter().any(|cmd| cmd.name == "ls"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn spaceworkfile_commands_shadow_runfile_ones(
+    ) -> Result<(), Box<dyn Error>> {
+        let workspace_root = TempDir::new("runfile")?;
+        let root = workspace_root.join("cpp/hello");
+        fs::create_dir_all(&root)?;
+        fs::write(
+            workspace_root.join(RUNFILE),
+            "[[cmd]]\nname = \"lint\"\nbin = \"lint\"\n\n\
+            [[cmd]]\nname = \"hi\"\nbin = \"echo\"\n",
+        )?;
+        fs::write(
+            root.join(RUNFILE),
+            "[[cmd]]\nname = \"test\"\nbin = \"make\"\nargs = \"test\"\n\n\
+            [[cmd]]\nname = \"lint\"\nbin = \"cppcheck\"\n",
+        )?;
+
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n\n\
+            [[cmd]]\nname = \"test\"\nbin = \"ctest\"\n",
+        )?;
+        cfg.root = root.clone();
+
+        let cmds = commands_in(Some(&workspace_root), Some(&cfg))?;
+
+        let find = |name: &str| cmds.iter().find(|(_, c)| c.name == name);
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(find("test").unwrap().1.bin, "ctest");
+        assert_eq!(find("lint").unwrap().0, Origin::Project);
+        assert_eq!(find("lint").unwrap().1.bin, "cppcheck");
+        assert_eq!(find("hi").unwrap().0, Origin::Global);
+
+        Ok(())
+    }
+
+    fn cmds(runfile: &str) -> Result<Vec<(Origin, Cmd)>, Box<dyn Error>> {
+        let runfile: RunFile = toml::from_str(runfile)?;
+
+        Ok(runfile
+            .cmd
+            .into_iter()
+            .map(|cmd| (Origin::Project, cmd))
+            .collect())
+    }
+
+    fn names(steps: &[Step]) -> Vec<&str> {
+        steps.iter().map(|step| step.name()).collect()
+    }
+
+    #[test]
+    fn dependencies_run_first_and_once() -> Result<(), Box<dyn Error>> {
+        let cmds = cmds(
+            "[[cmd]]\nname = \"test\"\nbin = \"./bin/debug/test\"\n\
+            depends = [\"build\", \"gen\"]\n\
+            [[cmd]]\nname = \"gen\"\nbin = \"./gen.sh\"\n\
+            depends = [\"fetch\"]\n\
+            [[cmd]]\nname = \"fetch\"\nbin = \"curl\"\n\
+            [[cmd]]\nname = \"bench\"\nbin = \"./bench.sh\"\n\
+            depends = [\"gen\", \"build\"]\n",
+        )?;
+
+        assert_eq!(
+            names(&resolve(&cmds, &["test"])?),
+            ["build", "fetch", "gen", "test"]
+        );
+        assert_eq!(resolve(&cmds, &["test"])?[0], Step::Build);
+        assert_eq!(
+            names(&resolve(&cmds, &["test", "bench", "gen"])?),
+            ["build", "fetch", "gen", "test", "bench"]
+        );
+        assert_eq!(names(&resolve(&cmds, &["fetch"])?), ["fetch"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn extra_args_bound_to_variable_or_appended(
+    ) -> Result<(), Box<dyn Error>> {
+        let cmds = cmds(
+            "[[cmd]]\nname = \"test\"\nbin = \"ctest\"\nargs = \"-R\"\n\
+            [[cmd]]\nname = \"wrapped\"\nbin = \"sh\"\n\
+            args = [\"-c\", \"./test ${args} | tee log\", \"${args}\",\n\
+            \"end\"]\n",
+        )?;
+        let extra = vec!["one case".to_string(), "-v".to_string()];
+        let no_args = vars(None, &[])?;
+        let vars = vars(None, &extra)?;
+
+        assert_eq!(
+            expand(&cmds[0].1, &vars, &extra)?,
+            ["ctest", "-R", "one case", "-v"]
+        );
+        assert_eq!(expand(&cmds[1].1, &vars, &extra)?, [
+            "sh",
+            "-c",
+            "./test 'one case' -v | tee log",
+            "one case",
+            "-v",
+            "end",
+        ]);
+        assert_eq!(expand(&cmds[1].1, &no_args, &[])?[2..], [
+            "./test  | tee log",
+            "end",
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shell_commands_run_with_sh() -> Result<(), Box<dyn Error>> {
+        let cmds = cmds(
+            "[[cmd]]\nname = \"piped\"\nshell = true\n\
+            bin = \"make_input\"\nargs = \"| ./bin/debug/main > out.txt\"\n\
+            [[cmd]]\nname = \"quoted\"\nshell = true\nbin = \"echo\"\n\
+            args = [\"a  b\", \"${args}\", \"$2\"]\n\
+            [[cmd]]\nname = \"script\"\n\
+            script = \"\"\"\necho \"$args\" | tr a-z A-Z\necho \"$2\"\n\"\"\"\n\
+            [[cmd]]\nname = \"both\"\nbin = \"ls\"\nscript = \"ls\"\n\
+            [[cmd]]\nname = \"neither\"\n",
+        )?;
+        let extra = vec!["one".to_string(), "two".to_string()];
+        let vars = vars(None, &extra)?;
+
+        assert_eq!(expand(&cmds[0].1, &vars, &[])?, [
+            "sh",
+            "-c",
+            "make_input | ./bin/debug/main > out.txt",
+            "piped",
+        ]);
+
+        let finished = run_in(&cmds[1].1, None, &vars, &extra)?;
+        assert_eq!(finished.output.stdout, b"a  b one two two\n");
+        let finished = run_in(&cmds[2].1, None, &vars, &extra)?;
+        assert_eq!(finished.output.stdout, b"ONE TWO\ntwo\n");
+
+        assert!(expand(&cmds[3].1, &vars, &[]).is_err());
+        assert!(expand(&cmds[4].1, &vars, &[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn commands_run_in_project_root_with_env_and_timeout(
+    ) -> Result<(), Box<dyn Error>> {
+        let mut cfg =
+            SpaceworkFile::from_str("[workspace]\nlanguage = \"C++\"\n")?;
+        cfg.root = PathBuf::from("/home/marsha/spacework/cpp/hello");
+
+        let cmds = cmds(
+            "[[cmd]]\nname = \"root\"\nbin = \"pwd\"\n\
+            [[cmd]]\nname = \"src\"\nbin = \"pwd\"\ncwd = \"src\"\n\
+            env = { GREETING = \"hi ${args}\" }\ntimeout = 5\n",
+        )?;
+        let (root, src) = (&cmds[0].1, &cmds[1].1);
+        let vars = vars(None, &["there".to_string()])?;
+
+        assert_eq!(
+            working_dir(root, Some(&cfg), &vars)?,
+            Some(cfg.root.clone())
+        );
+        assert_eq!(
+            working_dir(src, Some(&cfg), &vars)?,
+            Some(cfg.root.join("src"))
+        );
+        assert_eq!(working_dir(root, None, &vars)?, None);
+        assert_eq!(vars.expand_one(&src.env["GREETING"])?, "hi there");
+        assert_eq!(src.timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(root.timeout(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn subcommand_names_rejected() -> Result<(), Box<dyn Error>> {
+        let root = TempDir::new("reserved")?;
+        fs::write(
+            root.join(RUNFILE),
+            "[[cmd]]\nname = \"lint\"\nbin = \"cppcheck\"\n\
+            [[cmd]]\nname = \"history\"\nbin = \"git\"\nargs = \"log\"\n",
+        )?;
+        let runfile = read_runfile(&root.join(RUNFILE));
+
+        fs::write(root.join(RUNFILE), "")?;
+        let mut cfg = SpaceworkFile::from_str(
+            "[workspace]\nlanguage = \"C++\"\n\n\
+            [[cmd]]\nname = \"run\"\nbin = \"./run.sh\"\n",
+        )?;
+        cfg.root = root.to_path_buf();
+        let spaceworkfile = commands_in(None, Some(&cfg));
+
+        let err = runfile.unwrap_err().to_string();
+        assert!(err.contains("`history`"), "{}", err);
+        assert!(err.contains(&root.join(RUNFILE).display().to_string()));
+        let err = spaceworkfile.unwrap_err().to_string();
+        assert!(err.contains("`run`"), "{}", err);
+        assert!(err.contains("spacework.toml"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_cycles_and_unknown_commands_rejected(
+    ) -> Result<(), Box<dyn Error>> {
+        let cmds = cmds(
+            "[[cmd]]\nname = \"a\"\nbin = \"true\"\ndepends = [\"b\"]\n\
+            [[cmd]]\nname = \"b\"\nbin = \"true\"\ndepends = [\"c\"]\n\
+            [[cmd]]\nname = \"c\"\nbin = \"true\"\ndepends = [\"a\"]\n\
+            [[cmd]]\nname = \"d\"\nbin = \"true\"\ndepends = [\"nope\"]\n\
+            [[cmd]]\nname = \"e\"\nbin = \"true\"\ndepends = [\"run\"]\n",
+        )?;
+
+        let err = resolve(&cmds, &["a"]).unwrap_err().to_string();
+        assert!(err.contains("a -> b -> c -> a"), "{}", err);
+        assert!(resolve(&cmds, &["d"]).is_err());
+        assert!(resolve(&cmds, &["nope"]).is_err());
+        // Only `build` of spacework's own commands can be depended on.
+        assert!(resolve(&cmds, &["e"]).is_err());
+
+        Ok(())
     }
 }
-*/
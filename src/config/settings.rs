@@ -0,0 +1,103 @@
+use crate::spacework::workspace;
+
+use serde::Deserialize;
+
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+/// spacework's own settings, from `~/spacework/config.toml`. Everything is
+/// optional, and a missing file means the defaults.
+#[derive(Debug, Default, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub history: HistorySettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistorySettings {
+    #[serde(default)]
+    pub backend: Backend,
+    /// The most entries to keep. Older ones are moved to archives.
+    pub max_entries: Option<usize>,
+    /// How long to keep entries for, e.g. `90d`. Older ones are moved to
+    /// archives.
+    pub max_age: Option<String>,
+}
+
+/// Where history is kept.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// `~/.spacework_history.db`
+    #[default]
+    Sqlite,
+    /// `~/.spacework_history`, one JSON record per line.
+    File,
+}
+
+impl Settings {
+    const FILE: &'static str = "config.toml";
+
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = workspace::workspace_dir()?.join(Self::FILE);
+        let settings = match fs::read_to_string(&path) {
+            Ok(settings) => settings,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Unable to read {}: {}",
+                    path.display(),
+                    e
+                )
+                .into())
+            }
+        };
+
+        match toml::from_str(&settings) {
+            Ok(settings) => Ok(settings),
+            Err(e) => Err(
+                format!("Unable to parse {}: {}", path.display(), e).into()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_backend_defaults_to_sqlite() -> Result<(), Box<dyn Error>> {
+        let settings: Settings = toml::from_str("")?;
+        assert_eq!(settings.history.backend, Backend::Sqlite);
+
+        let settings: Settings =
+            toml::from_str("[history]\nbackend = \"file\"\n")?;
+        assert_eq!(settings.history.backend, Backend::File);
+
+        assert!(toml::from_str::<Settings>(
+            "[history]\nbackend = \"postgres\"\n"
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_kept_forever_by_default() -> Result<(), Box<dyn Error>> {
+        let settings: Settings = toml::from_str("")?;
+        assert_eq!(settings.history.max_entries, None);
+        assert_eq!(settings.history.max_age, None);
+
+        let settings: Settings = toml::from_str(
+            "[history]\nmax_entries = 5000\nmax_age = \"26w\"\n",
+        )?;
+        assert_eq!(settings.history.max_entries, Some(5000));
+        assert_eq!(settings.history.max_age.as_deref(), Some("26w"));
+
+        Ok(())
+    }
+}
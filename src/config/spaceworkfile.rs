@@ -1,16 +1,32 @@
-use crate::config::languagefile::LanguageFile;
+use crate::config::languagefile::{LanguageFile, Profile};
+use crate::config::runfile;
 use crate::spacework::workspace;
 
 use serde::Deserialize;
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize)]
 pub struct SpaceworkFile {
     pub workspace: Workspace,
+    #[serde(default)]
+    pub build: Build,
+
+    /// Added to, or replacing, the language's profiles.
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+
+    /// Runfile commands, shadowing ones in the project's `runfile.toml`.
+    #[serde(default)]
+    pub cmd: Vec<runfile::Cmd>,
+
+    /// Directory the `spacework.toml` file was found in.
+    #[serde(skip)]
+    pub root: PathBuf,
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,6 +34,30 @@ pub struct Workspace {
     pub language: String,
 }
 
+/// Per-project changes to the build command a language file puts together
+/// from its `[language]` fields. Unused when the language sets `cmd.build`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Build {
+    pub compiler: Option<String>,
+    pub standard: Option<String>,
+
+    /// Added after the language's own warnings.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+
+    /// Glob patterns, relative to the project root, narrowing down which of
+    /// the sources in `src/` get built. Everything is included when empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// How many sources to compile at once. Defaults to the number of CPUs.
+    pub jobs: Option<usize>,
+}
+
 // const DEFAULT_TOML: &'static str = include_str!("../../spacework.toml");
 
 impl SpaceworkFile {
@@ -28,8 +68,11 @@ impl SpaceworkFile {
     pub fn find_in_dir(dir: &mut PathBuf) -> Result<Self, Box<dyn Error>> {
         if !workspace::is_inside_workspace(dir)? {
             Err("Must be inside a spacework workspace".into())
-        } else if let Ok(cfg) = fs::read_to_string("spacework.toml") {
-            Ok(Self::from_str(&cfg)?)
+        } else if let Ok(cfg) = fs::read_to_string(dir.join("spacework.toml"))
+        {
+            let mut cfg = Self::from_str(&cfg)?;
+            cfg.root = dir.clone();
+            Ok(cfg)
         } else if dir.pop() {
             Self::find_in_dir(dir)
         } else {
@@ -37,6 +80,51 @@ impl SpaceworkFile {
         }
     }
 
+    /// The project containing `dir`, or `None` if it isn't inside one. A
+    /// `spacework.toml` that can't be read or parsed is an error.
+    pub fn find(dir: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        if !workspace::is_inside_workspace(dir)? {
+            return Ok(None);
+        }
+
+        Self::find_upward(dir)
+    }
+
+    /// The nearest `spacework.toml` in `dir` or above it.
+    fn find_upward(dir: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        for dir in dir.ancestors() {
+            let path = dir.join("spacework.toml");
+            let cfg = match fs::read_to_string(&path) {
+                Ok(cfg) => cfg,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(format!(
+                        "Unable to read {}: {}",
+                        path.display(),
+                        e
+                    )
+                    .into())
+                }
+            };
+            let mut cfg = match Self::from_str(&cfg) {
+                Ok(cfg) => cfg,
+                Err(e) => {
+                    return Err(format!(
+                        "Unable to parse {}: {}",
+                        path.display(),
+                        e
+                    )
+                    .into())
+                }
+            };
+            cfg.root = dir.to_path_buf();
+
+            return Ok(Some(cfg));
+        }
+
+        Ok(None)
+    }
+
     pub fn create(
         dir: &Path,
         langfile: &LanguageFile,
@@ -49,3 +137,31 @@ impl SpaceworkFile {
         Ok(cfg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn broken_project_files_not_ignored() -> Result<(), Box<dyn Error>> {
+        let root = TempDir::new("find")?;
+        let src = root.join("hello/src");
+        fs::create_dir_all(&src)?;
+        fs::write(
+            root.join("hello/spacework.toml"),
+            "[workspace]\nlanguage = \"C++\"\n",
+        )?;
+
+        let found = SpaceworkFile::find_upward(&src)?.map(|cfg| cfg.root);
+        fs::write(root.join("hello/spacework.toml"), "[workspace\n")?;
+        let broken = SpaceworkFile::find_upward(&src);
+        let outside = SpaceworkFile::find(&root)?;
+
+        assert_eq!(found, Some(root.join("hello")));
+        assert!(broken.is_err());
+        assert!(outside.is_none());
+
+        Ok(())
+    }
+}
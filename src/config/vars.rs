@@ -0,0 +1,354 @@
+use crate::config::command;
+use crate::config::languagefile::LanguageFile;
+use crate::config::spaceworkfile::SpaceworkFile;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+
+/// Values for the `${name}` placeholders in language file and runfile
+/// commands. `${env:NAME}` reads the environment variable `NAME`, and `$$`
+/// is a literal `$`.
+#[derive(Clone, Debug, Default)]
+pub struct Vars {
+    values: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    One(String),
+    /// Becomes one argument per item when it's a whole argument on its own,
+    /// e.g. `${sources}`. Otherwise the items are joined by spaces, and
+    /// quoted the way a shell would need if they have spaces or the like,
+    /// so e.g. `sh -c "./test ${args}"` gets each of them whole.
+    Many(Vec<String>),
+}
+
+/// A piece of an argument.
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    /// The name of a variable, e.g. `src` for `${src}`.
+    Var(&'a str),
+}
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every variable available to a project's commands:
+    ///
+    /// - `${src}`: the language's main source file, e.g. `main.cpp`
+    /// - `${sources}`: every source file that gets built, e.g. `./src/a.cpp`
+    /// - `${out}`: the binary's file name, e.g. `main`
+    /// - `${project}`: the project's name
+    /// - `${project_dir}`: the project's root directory
+    /// - `${bin_dir}`: the directory `profile`'s binaries go in
+    /// - `${profile}`: the name of the profile
+    pub fn project(
+        cfg: &SpaceworkFile,
+        langfile: &LanguageFile,
+        profile: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut vars = Self::new();
+        vars.set("src", &langfile.workspace.src)
+            .set("out", &langfile.outfile()?)
+            .set("project_dir", &cfg.root.to_string_lossy())
+            .set(
+                "bin_dir",
+                &cfg.root.join("bin").join(profile).to_string_lossy(),
+            )
+            .set("profile", profile);
+        if let Some(name) = cfg.root.file_name() {
+            vars.set("project", &name.to_string_lossy());
+        }
+        // Not every language's files are found by `sources`, e.g. when its
+        // `extensions` don't match, so this one is left out when there are
+        // none rather than failing every command.
+        if let Ok(sources) = langfile.sources(cfg) {
+            vars.set_many(
+                "sources",
+                sources
+                    .iter()
+                    .map(|src| format!("./{}", src.display()))
+                    .collect(),
+            );
+        }
+
+        Ok(vars)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> &mut Self {
+        self.values
+            .insert(name.to_string(), Value::One(value.to_string()));
+        self
+    }
+
+    pub fn set_many(&mut self, name: &str, values: Vec<String>) -> &mut Self {
+        self.values.insert(name.to_string(), Value::Many(values));
+        self
+    }
+
+    /// Each variable's name and value, with lists joined by spaces.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, String)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.joined()))
+    }
+
+    /// Replaces the variables in each argument. Fails listing every unknown
+    /// variable if there are any.
+    pub fn expand(
+        &self,
+        args: &[String],
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut expanded = Vec::new();
+        let mut unknown = Vec::new();
+
+        for arg in args {
+            if let Some(Value::Many(values)) = self.whole(arg) {
+                expanded.extend(values.iter().cloned());
+            } else {
+                expanded.push(self.expand_arg(arg, true, &mut unknown)?);
+            }
+        }
+
+        if unknown.is_empty() {
+            Ok(expanded)
+        } else {
+            Err(self.unknown(&unknown))
+        }
+    }
+
+    /// Replaces the variables in `value`, keeping it a single string, e.g.
+    /// for environment variables.
+    pub fn expand_one(&self, value: &str) -> Result<String, Box<dyn Error>> {
+        let mut unknown = Vec::new();
+        let expanded = self.expand_arg(value, false, &mut unknown)?;
+
+        if unknown.is_empty() {
+            Ok(expanded)
+        } else {
+            Err(self.unknown(&unknown))
+        }
+    }
+
+    fn unknown(&self, unknown: &[String]) -> Box<dyn Error> {
+        let mut available: Vec<String> = self
+            .values
+            .keys()
+            .map(|name| format!("${{{}}}", name))
+            .collect();
+        available.push("${env:NAME}".to_string());
+
+        format!(
+            "Unknown variables in command: {}. Available variables are: {}",
+            unknown.join(", "),
+            available.join(", ")
+        )
+        .into()
+    }
+
+    /// The variable `arg` consists of, if it's nothing but one variable.
+    fn whole(&self, arg: &str) -> Option<&Value> {
+        arg.strip_prefix("${")
+            .and_then(|arg| arg.strip_suffix('}'))
+            .filter(|name| !name.contains('}'))
+            .and_then(|name| self.values.get(name))
+    }
+
+    /// Whether any of `args` uses the variable `name`, e.g. `args` for
+    /// `${args}`. `$${args}` doesn't count, as it's a literal `${args}`.
+    pub fn uses(args: &[String], name: &str) -> Result<bool, Box<dyn Error>> {
+        for arg in args {
+            if tokens(arg)?.contains(&Token::Var(name)) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// `arg` with its variables replaced. Lists are quoted for a shell if
+    /// `quote` is set, see `Value::Many`.
+    fn expand_arg(
+        &self,
+        arg: &str,
+        quote: bool,
+        unknown: &mut Vec<String>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut expanded = String::new();
+        for token in tokens(arg)? {
+            let name = match token {
+                Token::Text(text) => {
+                    expanded.push_str(text);
+                    continue;
+                }
+                Token::Var(name) => name,
+            };
+
+            match self.lookup(name, quote) {
+                Some(value) => expanded.push_str(&value),
+                None => {
+                    let name = format!("${{{}}}", name);
+                    if !unknown.contains(&name) {
+                        unknown.push(name);
+                    }
+                }
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    fn lookup(&self, name: &str, quote: bool) -> Option<String> {
+        if let Some(var) = name.strip_prefix("env:") {
+            return env::var(var).ok();
+        }
+
+        match self.values.get(name)? {
+            Value::Many(values) if quote => Some(command::join(values)),
+            value => Some(value.joined()),
+        }
+    }
+}
+
+/// Splits `arg` into text and the variables in it.
+fn tokens(arg: &str) -> Result<Vec<Token<'_>>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut rest = arg;
+
+    while let Some(i) = rest.find('$') {
+        if i > 0 {
+            tokens.push(Token::Text(&rest[..i]));
+        }
+        rest = &rest[i + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            tokens.push(Token::Text("$"));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = match after.find('}') {
+                Some(end) => end,
+                None => {
+                    return Err(format!(
+                        "Missing `}}` after `${{` in `{}`",
+                        arg
+                    )
+                    .into())
+                }
+            };
+            tokens.push(Token::Var(&after[..end]));
+            rest = &after[end + 1..];
+        } else {
+            tokens.push(Token::Text("$"));
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+impl Value {
+    fn joined(&self) -> String {
+        match self {
+            Value::One(value) => value.clone(),
+            Value::Many(values) => values.join(" "),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn variables_expanded() -> Result<(), Box<dyn Error>> {
+        env::set_var("SPACEWORK_TEST_VAR", "from env");
+        let mut vars = Vars::new();
+        vars.set("src", "main.cpp")
+            .set("out", "main")
+            .set("profile", "debug")
+            .set_many("sources", args(&["./src/a.cpp", "./src/b.cpp"]));
+
+        assert_eq!(
+            vars.expand(&args(&[
+                "g++",
+                "-DSRC=\"SRC\"",
+                "${sources}",
+                "./src/${src}",
+                "-o",
+                "./bin/${profile}/${out}",
+                "${env:SPACEWORK_TEST_VAR}",
+                "$$HOME costs $5",
+                "[${sources}]",
+            ]))?,
+            args(&[
+                "g++",
+                "-DSRC=\"SRC\"",
+                "./src/a.cpp",
+                "./src/b.cpp",
+                "./src/main.cpp",
+                "-o",
+                "./bin/debug/main",
+                "from env",
+                "$HOME costs $5",
+                "[./src/a.cpp ./src/b.cpp]",
+            ])
+        );
+        assert_eq!(vars.expand_one("${sources}")?, "./src/a.cpp ./src/b.cpp");
+
+        Ok(())
+    }
+
+    #[test]
+    fn lists_quoted_inside_arguments() -> Result<(), Box<dyn Error>> {
+        let mut vars = Vars::new();
+        vars.set_many("args", args(&["one case", "-v", "it's"]));
+
+        assert_eq!(
+            vars.expand(&args(&["${args}", "./test ${args} | tee log"]))?,
+            args(&[
+                "one case",
+                "-v",
+                "it's",
+                "./test 'one case' -v 'it'\\''s' | tee log",
+            ])
+        );
+        assert_eq!(vars.expand_one("${args}")?, "one case -v it's");
+
+        assert!(Vars::uses(&args(&["ctest", "-R ${args}"]), "args")?);
+        assert!(!Vars::uses(&args(&["echo", "$${args}"]), "args")?);
+        assert!(!Vars::uses(&args(&["echo", "${argsx}"]), "args")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_variables_listed() {
+        let mut vars = Vars::new();
+        vars.set("src", "main.cpp");
+
+        let err = vars
+            .expand(&args(&[
+                "${src}",
+                "${nope}",
+                "${env:SPACEWORK_SURELY_UNSET}",
+                "${nope}",
+            ]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("${nope}, ${env:SPACEWORK_SURELY_UNSET}."));
+        assert!(err.contains("${src}"));
+
+        assert!(vars.expand(&args(&["${src"])).is_err());
+        assert!(vars.expand_one("${src} ${nope}").is_err());
+    }
+}
@@ -1,17 +1,40 @@
 mod config;
 mod spacework;
-use spacework::history::{self, History};
+#[cfg(test)]
+mod testing;
+use spacework::history::{
+    self, Action, Format, History, Project, Query, Record,
+};
+use spacework::process::exit;
 use spacework::workspace::{self, Workspace};
 
 use config::cli::CliArg;
-use config::runfile;
+use config::command;
+use config::languagefile;
+use config::runfile::{self, Origin, Step};
 
-use clap::App;
+use chrono::Local;
+use clap::{App, AppSettings, ArgMatches};
 
+use std::env;
 use std::error::Error;
-use std::str::{self, FromStr};
+use std::io;
+use std::process::{self, ExitStatus, Output};
+use std::str::FromStr;
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
+    process::exit(match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            exit::INTERNAL_ERROR
+        }
+    });
+}
+
+/// Does what was asked for on the command line, returning the exit code
+/// spacework should exit with. See `spacework::process::exit`.
+fn run() -> Result<i32, Box<dyn Error>> {
     let mut app = App::new("Spacework: A workspace manager")
         .subcommand(
             App::new("new")
@@ -20,77 +43,316 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .arg(CliArg::new_language()),
         )
         .subcommand(
-            App::new("build").about("Compile source code into a binary"),
+            App::new("build")
+                .about("Compile source code into a binary")
+                .arg(CliArg::build_profile())
+                .arg(CliArg::build_release())
+                .arg(CliArg::build_jobs()),
+        )
+        .subcommand(
+            App::new("run")
+                .about("Build the project if needed, then run it")
+                .arg(CliArg::build_profile())
+                .arg(CliArg::build_release())
+                .arg(CliArg::build_jobs())
+                .arg(CliArg::run_args()),
+        )
+        .subcommand(
+            App::new("commands")
+                .about("List the commands spacework <COMMAND> can run"),
+        )
+        .subcommand(
+            App::new("resume")
+                .about("Print the path of the project last worked on")
+                .arg(CliArg::resume_list())
+                .arg(CliArg::resume_count()),
         )
         .subcommand(
             App::new("history")
                 .about("View previous spacework actions")
                 .arg(CliArg::history_all())
-                .arg(CliArg::history_count()),
+                .arg(CliArg::history_count())
+                .arg(CliArg::history_format())
+                .arg(CliArg::history_project())
+                .arg(CliArg::history_since())
+                .arg(CliArg::history_until())
+                .arg(CliArg::history_action())
+                .arg(CliArg::history_failed())
+                .arg(CliArg::history_grep())
+                .setting(AppSettings::SubcommandsNegateReqs)
+                .subcommand(App::new("migrate").about(
+                    "Convert history written by older versions of spacework",
+                ))
+                .subcommand(
+                    App::new("prune")
+                        .about("Move old history entries into an archive")
+                        .arg(CliArg::prune_max_entries())
+                        .arg(CliArg::prune_max_age()),
+                ),
         )
         .arg(CliArg::command())
+        .arg(CliArg::command_args())
+        .arg(CliArg::dry_run())
         .arg(CliArg::purge());
 
-    let opts = app.get_matches_mut();
+    // Usage errors would exit with 2 otherwise, which a command could have
+    // exited with too.
+    let opts = match app.try_get_matches_from_mut(env::args_os()) {
+        Ok(opts) => opts,
+        Err(e) => {
+            e.print()?;
+            // `--help` and `--version` go through here too.
+            return Ok(if e.use_stderr() {
+                exit::INTERNAL_ERROR
+            } else {
+                exit::SUCCESS
+            });
+        }
+    };
 
     if let Some(opts) = opts.subcommand_matches("new") {
-        Workspace::create_from_options(
-            opts.value_of("name"),
-            opts.value_of("language"),
-        )?;
+        return recorded(Action::New, invocation(), |record| {
+            let workspace_root = workspace::workspace_dir()?;
+            if !workspace_root.exists() {
+                record.message = Some(format!(
+                    "Created spacework directory: {}",
+                    workspace_root.display()
+                ));
+            }
+
+            let proj_dir = Workspace::create_from_options(
+                opts.value_of("name"),
+                opts.value_of("language"),
+            )?;
+            record.set_project(&proj_dir);
 
-        return Ok(());
+            Ok(exit::SUCCESS)
+        });
     }
 
-    if let Some(opts) = opts.subcommand_matches("history") {
-        let history = History::new()?;
+    if opts.subcommand_matches("commands").is_some() {
+        let cmds = runfile::available()?;
+        for (name, description) in runfile::BUILT_INS {
+            println!("{} ({})", name, Origin::BuiltIn);
+            println!("    {}", description);
+            println!("    $ spacework {}", name);
+        }
+        for (origin, cmd) in cmds {
+            println!("{} ({})", cmd.name, origin);
+            if let Some(description) = &cmd.description {
+                println!("    {}", description);
+            }
+            match runfile::command_line(&cmd, &[]) {
+                Ok(args) => println!("    $ {}", command::join(&args)),
+                Err(e) => println!("    Error: {}", e),
+            }
+        }
 
-        if opts.is_present("all") {
-            print!("{}", history.read_all()?);
-        } else if let Some(count) = opts.value_of("count") {
-            for line in history.read_last(usize::from_str(count)?)?.iter() {
-                println!("{}", line);
+        return Ok(exit::SUCCESS);
+    }
+
+    if let Some(opts) = opts.subcommand_matches("resume") {
+        let history = History::new()?;
+        if opts.is_present("list") {
+            let count = match opts.value_of("count") {
+                Some(count) => usize::from_str(count)?,
+                None => 10,
+            };
+            for record in history.recent_projects(count)? {
+                println!("{}", record);
             }
+
+            return Ok(exit::SUCCESS);
+        }
+
+        // The path alone goes to stdout, for `cd "$(spacework resume)"`.
+        let record = match history.recent_projects(1)?.pop() {
+            Some(record) => record,
+            None => return Err("No projects in history to resume".into()),
+        };
+        if let Some(project) = &record.project {
+            println!("{}", project.display());
         }
+        eprintln!("Last: {}", record);
 
-        return Ok(());
+        return Ok(exit::SUCCESS);
     }
 
-    if let Some(_opts) = opts.subcommand_matches("build") {
-        let cmd = workspace::build()?;
+    if let Some(opts) = opts.subcommand_matches("history") {
+        if opts.subcommand_matches("migrate").is_some() {
+            println!("Migrated {} history entries", history::migrate()?);
+            return Ok(exit::SUCCESS);
+        }
+
+        if let Some(opts) = opts.subcommand_matches("prune") {
+            let history = History::new()?;
+            let mut retention = history.retention();
+            if let Some(max) = opts.value_of("max-entries") {
+                retention.max_entries = Some(usize::from_str(max)?);
+            }
+            if let Some(age) = opts.value_of("max-age") {
+                retention.since = Some(history::parse_time(age, Local::now())?);
+            }
+            if retention.keeps_all() {
+                return Err("Nothing to prune by. Use --max-entries or \
+                    --max-age, or set max_entries or max_age in the \
+                    [history] section of ~/spacework/config.toml"
+                    .into());
+            }
 
-        if cmd.status.success() {
-            if let Ok(stdout) = str::from_utf8(&cmd.stdout) {
-                println!("{}", stdout);
+            match history.prune(&retention)? {
+                (0, _) => println!("No history entries to prune"),
+                (pruned, archives) => println!(
+                    "Moved {} history entries to {}",
+                    pruned,
+                    archives
+                        .iter()
+                        .map(|archive| archive.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
             }
-        } else if let Ok(stderr) = str::from_utf8(&cmd.stderr) {
-            eprintln!("`build` command exited with an error.\n");
-            eprintln!("{}", stderr);
-            eprintln!("{}", &cmd.status);
+
+            return Ok(exit::SUCCESS);
         }
 
-        return Ok(());
+        let history = History::new()?;
+        let mut query = history_query(opts)?;
+        let records = if opts.is_present("all") {
+            // Oldest first, like reading the history file.
+            let mut records = history.query(&query)?;
+            records.reverse();
+            records
+        } else {
+            query.limit = Some(match opts.value_of("count") {
+                Some(count) => usize::from_str(count)?,
+                None => 10,
+            });
+            history.query(&query)?
+        };
+
+        let format = match opts.value_of("format") {
+            Some(format) => Format::from_str(format)?,
+            None => Format::Text,
+        };
+        format.write(&records, io::stdout().lock())?;
+
+        return Ok(exit::SUCCESS);
+    }
+
+    if let Some(opts) = opts.subcommand_matches("build") {
+        return recorded(Action::Build, invocation(), |_| {
+            match workspace::build(profile(opts), jobs(opts)?)? {
+                Some(cmd) => Ok(print_build_output(&cmd)),
+                None => {
+                    println!("Build is up to date");
+                    Ok(exit::SUCCESS)
+                }
+            }
+        });
+    }
+
+    if let Some(opts) = opts.subcommand_matches("run") {
+        return recorded(Action::Run, invocation(), |_| {
+            let (mut cfg, langfile) = workspace::current_project()?;
+            let profile = profile(opts);
+            if let Some(jobs) = jobs(opts)? {
+                cfg.build.jobs = Some(jobs);
+            }
+
+            if langfile.builds_before_run() {
+                if let Some(cmd) = langfile.build(&cfg, profile)? {
+                    if !cmd.status.success() {
+                        return Ok(print_build_output(&cmd));
+                    }
+                }
+            }
+
+            let status = langfile.run(&cfg, profile, &args(opts))?;
+
+            Ok(exit::code(status))
+        });
     }
 
+    // Not recorded, which would start the history it deletes over again.
     if opts.is_present("purge") {
         workspace::delete_workspace()?;
-        history::delete_history_file()?;
-        println!("Deleted spacework directory and .spacework_history file");
+        history::delete_history()?;
+        println!("Deleted spacework directory and history");
 
-        return Ok(());
+        return Ok(exit::SUCCESS);
     }
 
     if let Some(cmds) = opts.values_of("command") {
-        for cmd in cmds {
-            let output = runfile::run(cmd)?;
-            if output.status.success() {
-                if let Ok(stdout) = str::from_utf8(&output.stdout) {
-                    print!("{}", stdout);
+        let cmds: Vec<&str> = cmds.collect();
+        let steps = runfile::plan(&cmds)?;
+
+        // Only the commands asked for get the extra arguments, not the ones
+        // they depend on.
+        let extra_args = args(&opts);
+        let args_for = |step: &Step| {
+            if cmds.contains(&step.name()) {
+                extra_args.clone()
+            } else {
+                Vec::new()
+            }
+        };
+
+        if opts.is_present("dry-run") {
+            for (i, step) in steps.iter().enumerate() {
+                match step {
+                    Step::Build => {
+                        println!("{}. build (spacework build)", i + 1)
+                    }
+                    Step::Cmd(cmd) => println!(
+                        "{}. {}: {}",
+                        i + 1,
+                        cmd.name,
+                        command::join(&runfile::command_line(
+                            cmd,
+                            &args_for(step)
+                        )?)
+                    ),
+                }
+            }
+
+            return Ok(exit::SUCCESS);
+        }
+
+        for step in steps {
+            let code = match &step {
+                Step::Build => {
+                    let command = "spacework build".to_string();
+                    recorded(Action::Build, command, |_| {
+                        let profile = languagefile::DEFAULT_PROFILE;
+                        match workspace::build(profile, None)? {
+                            Some(output) => Ok(exited("build", output.status)),
+                            None => Ok(exit::SUCCESS),
+                        }
+                    })?
+                }
+                Step::Cmd(cmd) => {
+                    let args = args_for(&step);
+                    let command =
+                        command::join(&runfile::command_line(cmd, &args)?);
+                    recorded(Action::Command, command, |_| {
+                        let finished = runfile::run(cmd, &args)?;
+                        if finished.timed_out {
+                            eprintln!(
+                                "`{}` timed out after {}s and was killed",
+                                cmd.name,
+                                cmd.timeout.unwrap_or_default()
+                            );
+                            return Ok(exit::TIMED_OUT);
+                        }
+
+                        Ok(exited(&cmd.name, finished.output.status))
+                    })?
                 }
-            } else if let Ok(stderr) = str::from_utf8(&output.stderr) {
-                eprintln!("`{}` exited with an error.\n", cmd);
-                eprintln!("{}", stderr);
-                eprintln!("{}", output.status);
+            };
+
+            if code != exit::SUCCESS {
+                return Ok(code);
             }
         }
     } else {
@@ -99,5 +361,131 @@ fn main() -> Result<(), Box<dyn Error>> {
         app.print_help()?;
     }
 
-    Ok(())
+    Ok(exit::SUCCESS)
+}
+
+/// Does `action`, then notes in history how it went and how long it took.
+/// `f` can fill in more of the record, e.g. a project it created.
+fn recorded(
+    action: Action,
+    command: String,
+    f: impl FnOnce(&mut Record) -> Result<i32, Box<dyn Error>>,
+) -> Result<i32, Box<dyn Error>> {
+    let mut record = Record {
+        command: Some(command),
+        ..Record::new(action)
+    }
+    .in_current_project();
+
+    let result = f(&mut record);
+    let status = match &result {
+        Ok(code) => *code,
+        Err(_) => exit::INTERNAL_ERROR,
+    };
+
+    // Not being able to write history shouldn't get in the way of
+    // anything else, so it's only a warning. Pruning is kept apart, as the
+    // record's written whether or not it works.
+    let written = History::new().and_then(|history| {
+        history.write(&record.finish(status))?;
+        Ok(history)
+    });
+    match written {
+        Ok(history) => {
+            if let Err(e) = history.prune_if_due() {
+                eprintln!("Warning: Unable to prune history: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Warning: Unable to write history: {}", e),
+    }
+
+    result
+}
+
+/// How spacework was run, for history.
+fn invocation() -> String {
+    let mut args: Vec<String> = env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    if let Some(program) = args.first_mut() {
+        *program = "spacework".to_string();
+    }
+
+    command::join(&args)
+}
+
+/// Reports `name` failing, if it did, returning the exit code to use.
+fn exited(name: &str, status: ExitStatus) -> i32 {
+    if !status.success() {
+        eprintln!("`{}` exited with an error: {}", name, status);
+    }
+
+    exit::code(status)
+}
+
+fn profile(opts: &ArgMatches) -> &str {
+    if opts.is_present("release") {
+        "release"
+    } else {
+        opts.value_of("profile").unwrap_or(languagefile::DEFAULT_PROFILE)
+    }
+}
+
+/// The history `spacework history`'s filters ask for.
+fn history_query(opts: &ArgMatches) -> Result<Query, Box<dyn Error>> {
+    let now = Local::now();
+    let time = |name, parse: fn(_, _) -> _| -> Result<_, Box<dyn Error>> {
+        match opts.value_of(name) {
+            Some(time) => Ok(Some(parse(time, now)?)),
+            None => Ok(None),
+        }
+    };
+
+    Ok(Query {
+        action: match opts.value_of("action") {
+            Some(action) => Some(action.parse()?),
+            None => None,
+        },
+        project: match opts.value_of("project") {
+            Some(project) => Some(Project::from_arg(project)?),
+            None => None,
+        },
+        since: time("since", history::parse_time)?,
+        until: time("until", history::parse_until)?,
+        failed: opts.is_present("failed"),
+        grep: opts.value_of("grep").map(|text| text.to_string()),
+        limit: None,
+    })
+}
+
+/// Everything after `--`.
+fn args(opts: &ArgMatches) -> Vec<String> {
+    match opts.values_of("args") {
+        Some(args) => args.map(|arg| arg.to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn jobs(opts: &ArgMatches) -> Result<Option<usize>, Box<dyn Error>> {
+    match opts.value_of("jobs") {
+        Some(jobs) => match usize::from_str(jobs) {
+            Ok(jobs) if jobs > 0 => Ok(Some(jobs)),
+            _ => Err(format!(
+                "`--jobs` must be a number greater than 0, not `{}`",
+                jobs
+            )
+            .into()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The build's output has already been shown as it ran, so this only
+/// reports how it went, returning the exit code to use.
+fn print_build_output(cmd: &Output) -> i32 {
+    if !cmd.status.success() {
+        eprintln!("`build` command exited with an error: {}", cmd.status);
+    }
+
+    exit::code(cmd.status)
 }
@@ -1,2 +1,4 @@
+pub mod build;
 pub mod history;
+pub mod process;
 pub mod workspace;
@@ -0,0 +1,361 @@
+use crate::config::languagefile::LanguageFile;
+use crate::config::spaceworkfile::SpaceworkFile;
+use crate::spacework::process;
+
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Where object files, depfiles and recorded commands for `profile` go,
+/// relative to the project root.
+pub fn build_dir(profile: &str) -> PathBuf {
+    Path::new("build").join(profile)
+}
+
+/// Compiles each source whose object file is missing or older than the
+/// source or any header it includes, then links the objects into the binary
+/// if anything changed. Returns `None` when there was nothing to do.
+pub fn incremental(
+    langfile: &LanguageFile,
+    cfg: &SpaceworkFile,
+    profile: &str,
+) -> Result<Option<Output>, Box<dyn Error>> {
+    let profile_flags = langfile.profile(cfg, profile)?;
+    let build_dir = build_dir(profile);
+
+    let mut objects = Vec::new();
+    let mut stale = Vec::new();
+    for src in langfile.sources(cfg)? {
+        let obj = build_dir.join(format!("{}.o", src.display()));
+        let args = langfile.compile_command(cfg, &profile_flags, &src, &obj);
+
+        let inputs = if langfile.language.depfile.is_empty() {
+            Some(vec![src.clone()])
+        } else {
+            read_depfile(&cfg.root, &obj.with_extension("d"))?
+        };
+
+        if is_stale(&cfg.root, &obj, inputs.as_deref(), &args)? {
+            if let Some(dir) = cfg.root.join(&obj).parent() {
+                fs::create_dir_all(dir)?;
+            }
+            stale.push((obj.clone(), args));
+        }
+
+        objects.push(obj);
+    }
+
+    let mut outputs = Vec::new();
+    for ((obj, args), output) in stale.iter().zip(compile(&stale, cfg)?) {
+        let success = output.status.success();
+        outputs.push(output);
+        if success {
+            record_command(&cfg.root, obj, args)?;
+        }
+    }
+    if outputs.iter().any(|output| !output.status.success()) {
+        return Ok(Some(combine(outputs)));
+    }
+
+    let bin = langfile.binary(profile)?;
+    let args = langfile.link_command(cfg, &profile_flags, &objects, &bin);
+    if !outputs.is_empty() || is_stale(&cfg.root, &bin, Some(&objects), &args)?
+    {
+        let output = run(&args, &cfg.root)?;
+        let success = output.status.success();
+        outputs.push(output);
+        if success {
+            record_command(&cfg.root, &bin, &args)?;
+        }
+    }
+
+    if outputs.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(combine(outputs)))
+    }
+}
+
+/// Number of compilers to run at once: the project's `build.jobs`, or the
+/// number of CPUs.
+pub fn jobs(cfg: &SpaceworkFile) -> usize {
+    match cfg.build.jobs {
+        Some(jobs) => jobs.max(1),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    }
+}
+
+/// Runs the compile commands in `units` across `jobs(cfg)` threads,
+/// returning their output in the same order. Once one fails, no more are
+/// started, so the output may be shorter than `units`.
+fn compile(
+    units: &[(PathBuf, Vec<String>)],
+    cfg: &SpaceworkFile,
+) -> Result<Vec<Output>, Box<dyn Error>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs(cfg).min(units.len()) {
+            scope.spawn(|| loop {
+                if failed.load(Ordering::SeqCst) {
+                    break;
+                }
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let (_, args) = match units.get(i) {
+                    Some(unit) => unit,
+                    None => break,
+                };
+
+                let result = run(args, &cfg.root).map_err(|e| e.to_string());
+                if !matches!(&result, Ok(output) if output.status.success()) {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut outputs = Vec::new();
+    for (_, result) in results {
+        outputs.push(result?);
+    }
+
+    Ok(outputs)
+}
+
+/// Runs `args` from `dir`, showing its output as it goes and collecting a
+/// copy of it.
+pub fn run(args: &[String], dir: &Path) -> Result<Output, Box<dyn Error>> {
+    let (bin, args) = match args.split_first() {
+        Some((bin, args)) => (bin, args),
+        _ => return Err("Build command is empty".into()),
+    };
+
+    match process::run(Command::new(bin).args(args).current_dir(dir)) {
+        Ok(output) => Ok(output),
+        Err(e) => Err(format!("Unable to run `{}`: {}", bin, e).into()),
+    }
+}
+
+/// Whether `target` needs to be made again: it's missing, one of `inputs`
+/// is missing or newer than it, or it was last made with a different
+/// command. `None` means the inputs aren't known.
+fn is_stale(
+    root: &Path,
+    target: &Path,
+    inputs: Option<&[PathBuf]>,
+    args: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    let built = match fs::metadata(root.join(target)) {
+        Ok(metadata) => metadata.modified()?,
+        Err(_) => return Ok(true),
+    };
+
+    let inputs = match inputs {
+        Some(inputs) => inputs,
+        None => return Ok(true),
+    };
+
+    match fs::read_to_string(root.join(command_file(target))) {
+        Ok(recorded) if recorded == args.join("\n") => {}
+        _ => return Ok(true),
+    }
+
+    for input in inputs {
+        match fs::metadata(root.join(input)) {
+            Ok(metadata) if metadata.modified()? <= built => {}
+            _ => return Ok(true),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Where the command used to make `target` is kept, so changing flags in
+/// `spacework.toml` causes a rebuild.
+fn command_file(target: &Path) -> PathBuf {
+    match target.strip_prefix("bin") {
+        Ok(bin) => Path::new("build").join(bin).with_extension("cmd"),
+        Err(_) => target.with_extension("o.cmd"),
+    }
+}
+
+fn record_command(
+    root: &Path,
+    target: &Path,
+    args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(root.join(command_file(target)), args.join("\n"))?)
+}
+
+/// Files listed as prerequisites in a compiler-generated depfile, or `None`
+/// if it hasn't been written yet.
+fn read_depfile(
+    root: &Path,
+    depfile: &Path,
+) -> Result<Option<Vec<PathBuf>>, Box<dyn Error>> {
+    match fs::read_to_string(root.join(depfile)) {
+        Ok(contents) => Ok(Some(parse_depfile(&contents))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!(
+            "Unable to read depfile {}: {}",
+            depfile.display(),
+            e
+        )
+        .into()),
+    }
+}
+
+/// Parses the first rule of a Makefile-style depfile, `target: deps...`,
+/// with lines continued by `\` and spaces in paths escaped as `\ `.
+fn parse_depfile(contents: &str) -> Vec<PathBuf> {
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    let rule = contents.lines().next().unwrap_or("");
+    let deps = match rule.find(": ") {
+        Some(i) => &rule[i + 2..],
+        None => rule.trim_end_matches(':'),
+    };
+
+    let mut paths = Vec::new();
+    let mut path = String::new();
+    let mut chars = deps.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                path.push(' ');
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                if !path.is_empty() {
+                    paths.push(PathBuf::from(&path));
+                    path.clear();
+                }
+            }
+            c => path.push(c),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(PathBuf::from(path));
+    }
+
+    paths
+}
+
+/// Merges the output of several commands into one, with the status of the
+/// last command.
+fn combine(outputs: Vec<Output>) -> Output {
+    let mut combined = Output {
+        status: outputs[outputs.len() - 1].status,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    for output in outputs {
+        combined.stdout.extend(output.stdout);
+        combined.stderr.extend(output.stderr);
+    }
+
+    combined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn depfile_prerequisites_parsed() {
+        let depfile = "build/debug/src/main.cpp.o: src/main.cpp \\\n \
+            src/util/math.hpp /usr/include/c\\ files/x.h\n";
+
+        assert_eq!(
+            parse_depfile(depfile),
+            [
+                Path::new("src/main.cpp"),
+                Path::new("src/util/math.hpp"),
+                Path::new("/usr/include/c files/x.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_in_parallel_and_stops_after_a_failure(
+    ) -> Result<(), Box<dyn Error>> {
+        let mut cfg =
+            SpaceworkFile::from_str("[workspace]\nlanguage = \"C\"\n")?;
+        let root = TempDir::new("parallel")?;
+        cfg.root = root.to_path_buf();
+        cfg.build.jobs = Some(3);
+
+        let unit = |cmd: &str| {
+            (PathBuf::new(), vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                cmd.to_string(),
+            ])
+        };
+
+        let units: Vec<_> =
+            (0..8).map(|i| unit(&format!("echo {}", i))).collect();
+        let outputs = compile(&units, &cfg)?;
+        let stdout: Vec<_> =
+            outputs.iter().map(|o| o.stdout.clone()).collect();
+        let expected: Vec<_> =
+            (0..8).map(|i| format!("{}\n", i).into_bytes()).collect();
+        assert_eq!(stdout, expected);
+
+        cfg.build.jobs = Some(1);
+        let mut units = vec![unit("exit 1")];
+        units.extend((0..4).map(|_| unit("true")));
+        let outputs = compile(&units, &cfg)?;
+        assert_eq!(outputs.len(), 1);
+        assert!(!outputs[0].status.success());
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_when_inputs_newer_or_command_changed(
+    ) -> Result<(), Box<dyn Error>> {
+        let root = TempDir::new("stale")?;
+        fs::create_dir_all(root.join("build/debug"))?;
+
+        let now = SystemTime::now();
+        let touch = |path: &str, age: u64| -> Result<(), Box<dyn Error>> {
+            File::create(root.join(path))?
+                .set_modified(now - Duration::from_secs(age))?;
+            Ok(())
+        };
+        touch("main.cpp", 20)?;
+        touch("main.hpp", 20)?;
+        touch("build/debug/main.o", 10)?;
+
+        let obj = Path::new("build/debug/main.o");
+        let inputs = [PathBuf::from("main.cpp"), PathBuf::from("main.hpp")];
+        let args = vec!["g++".to_string(), "-c".to_string()];
+
+        assert!(is_stale(&root, obj, Some(&inputs), &args)?);
+        record_command(&root, obj, &args)?;
+        assert!(!is_stale(&root, obj, Some(&inputs), &args)?);
+        assert!(is_stale(&root, obj, None, &args)?);
+
+        let changed = vec!["g++".to_string(), "-O2".to_string()];
+        assert!(is_stale(&root, obj, Some(&inputs), &changed)?);
+
+        touch("main.hpp", 0)?;
+        assert!(is_stale(&root, obj, Some(&inputs), &args)?);
+
+        Ok(())
+    }
+}
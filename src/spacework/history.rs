@@ -1,127 +1,993 @@
+mod file;
+mod sqlite;
+
+use crate::config::settings::{Backend, HistorySettings, Settings};
+use crate::config::spaceworkfile::SpaceworkFile;
+
+use file::FileStore;
+use sqlite::SqliteStore;
+
+use std::collections::BTreeMap;
 use std::env::{self, VarError};
 use std::error::Error;
-use std::fs::{self, File, OpenOptions};
+use std::fmt;
+use std::fs::{self, OpenOptions};
 use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
-use std::str;
+use std::str::{self, FromStr};
+use std::time::Duration;
 
 use chrono::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
 
+/// spacework's history, kept by whichever `Store` the settings ask for.
 pub struct History {
-    histfile: PathBuf,
+    store: Box<dyn Store>,
+    retention: Retention,
 }
 
-impl History {
-    const HISTFILE: &'static str = ".spacework_history";
+/// Somewhere history records can be kept.
+pub trait Store {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error>>;
 
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        let home_dir = match env::var("HOME") {
-            Ok(home) => home,
-            Err(e) => match e {
-                VarError::NotPresent => return Err(
-                    "HOME environment variable not found. \
-                    Unable to find or create history file".into()
-                ),
-                VarError::NotUnicode(_) => return Err(
-                    "Unable to parse HOME environment variable: \
-                    Invalid unicode".into()
-                ),
-            },
-        };
-        
-        let histfile = Path::new(&home_dir).join(Self::HISTFILE);
-        if !histfile.exists() {
-            Self::create_history_file(&histfile)?;
+    /// The records `query` asks for, newest first.
+    fn query(&self, query: &Query) -> Result<Vec<Record>, Box<dyn Error>>;
+
+    /// The newest record of each project, newest first.
+    fn projects(&self) -> Result<Vec<Record>, Box<dyn Error>>;
+
+    /// Removes the records `retention` doesn't keep, returning how many.
+    /// They're passed to `archive`, oldest first, before they're removed,
+    /// and aren't removed if it fails.
+    fn prune(
+        &self,
+        retention: &Retention,
+        archive: &mut Archive,
+    ) -> Result<usize, Box<dyn Error>>;
+}
+
+/// Somewhere `Store::prune` puts what it removes.
+pub type Archive<'a> =
+    dyn FnMut(&[Record]) -> Result<(), Box<dyn Error>> + 'a;
+
+/// One thing spacework did.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    pub timestamp: DateTime<Local>,
+    pub action: Action,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The command line that was run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// The exit code spacework passed along, see `process::exit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Anything else worth knowing, e.g. the text of a migrated line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    New,
+    Build,
+    Run,
+    /// A runfile command.
+    Command,
+    /// Lines from old history files that don't say what they were.
+    Other,
+}
+
+/// How `spacework history` shows records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One line each, for people.
+    Text,
+    /// An array of objects with the fields of `Record`.
+    Json,
+    /// A header, then a row each. Empty fields are blank.
+    Csv,
+}
+
+/// Which records to read. Every field narrows it down further, and the
+/// default is every record.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub action: Option<Action>,
+    pub project: Option<Project>,
+    /// Only records from this time on.
+    pub since: Option<DateTime<Local>>,
+    /// Only records from before this time.
+    pub until: Option<DateTime<Local>>,
+    /// Only records of things that exited with an error.
+    pub failed: bool,
+    /// Only records whose project, language, command or message contains
+    /// this, ignoring ASCII case.
+    pub grep: Option<String>,
+    /// At most this many of the newest matching records.
+    pub limit: Option<usize>,
+}
+
+/// How much history to keep. The default is all of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Retention {
+    /// Keep at most this many of the newest records.
+    pub max_entries: Option<usize>,
+    /// Keep only records from this time on.
+    pub since: Option<DateTime<Local>>,
+}
+
+/// The project a `Query` asks for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Project {
+    Path(PathBuf),
+    /// Any project whose directory has this name.
+    Name(String),
+}
+
+impl Record {
+    pub fn new(action: Action) -> Self {
+        Record {
+            timestamp: Local::now(),
+            action,
+            project: None,
+            language: None,
+            command: None,
+            status: None,
+            duration_ms: None,
+            message: None,
+        }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms.map(Duration::from_millis)
+    }
+
+    /// Sets the project to the one containing the current directory, if
+    /// there is one.
+    pub fn in_current_project(mut self) -> Self {
+        if let Ok(dir) = env::current_dir() {
+            self.set_project(&dir);
         }
 
-        Ok(History { histfile })
+        self
     }
 
-    pub fn write<'a>(&self, text: &'a str) -> Result<&'a str, Box<dyn Error>> {
-        match self
-            .file()?
-            .write_all(
-            format!("{} {}\n", self.timestamp(), text).as_bytes())
-        {
-            Ok(_) => Ok(text),
-            Err(e) => Err(
-                format!("Unable to write to history file: {}", e).into()
-            ),
+    /// Sets the project to the one containing `dir`, if there is one.
+    pub fn set_project(&mut self, dir: &Path) {
+        let mut dir = dir.to_path_buf();
+        if let Ok(cfg) = SpaceworkFile::find_in_dir(&mut dir) {
+            self.project = Some(cfg.root);
+            self.language = Some(cfg.workspace.language);
         }
     }
-    
-    fn create_history_file(filepath: &Path) -> Result<(), Box<dyn Error>> {
-        match File::create(filepath) {
-            Ok(_) => {
-                Self::write(
-                    &Self{ histfile: filepath.into() }, 
-                    "Hello hello, world!",
-                )?;
-                println!(
-                    "Created spacework history file: {}",
-                    filepath.display()
-                );
-                Ok(())
-            },
-            Err(e) => Err(
-                format!("Unable to create history file: {}", e).into()
-            ),
+
+    /// Sets how it went, and how long it took since the record was made.
+    pub fn finish(mut self, status: i32) -> Self {
+        self.status = Some(status);
+        self.duration_ms = (Local::now() - self.timestamp)
+            .to_std()
+            .ok()
+            .map(|duration| duration.as_millis() as u64);
+
+        self
+    }
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::New => "new",
+            Action::Build => "build",
+            Action::Run => "run",
+            Action::Command => "command",
+            Action::Other => "other",
         }
     }
+}
+
+impl FromStr for Action {
+    type Err = Box<dyn Error>;
 
-    pub fn file(&self) -> Result<File, Box<dyn Error>> {
-        match OpenOptions::new().append(true).open(&self.histfile) {
-            Ok(file) => Ok(file),
-            Err(e) => return Err(
-                format!("Unable to open history file: {}", e).into()
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "new" => Ok(Action::New),
+            "build" => Ok(Action::Build),
+            "run" => Ok(Action::Run),
+            "command" => Ok(Action::Command),
+            "other" => Ok(Action::Other),
+            _ => Err(format!("Unknown history action `{}`", action).into()),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Lets `{:<7}` line actions up.
+        f.pad(self.as_str())
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<7}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.action
+        )?;
+        if let Some(project) = &self.project {
+            write!(f, "  {}", project.display())?;
+        }
+        if let Some(language) = &self.language {
+            write!(f, " ({})", language)?;
+        }
+        if let Some(command) = &self.command {
+            write!(f, "  $ {}", command)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, "  {}", message)?;
+        }
+
+        match (self.status, self.duration()) {
+            (Some(0), Some(duration)) => {
+                write!(f, "  [ok, {:.2}s]", duration.as_secs_f64())
+            }
+            (Some(status), Some(duration)) => write!(
+                f,
+                "  [exit {}, {:.2}s]",
+                status,
+                duration.as_secs_f64()
             ),
+            (Some(0), None) => write!(f, "  [ok]"),
+            (Some(status), None) => write!(f, "  [exit {}]", status),
+            (None, _) => Ok(()),
         }
     }
-    
-    pub fn read_last(
+}
+
+impl FromStr for Format {
+    type Err = Box<dyn Error>;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("Unknown history format `{}`", format).into()),
+        }
+    }
+}
+
+impl Format {
+    const CSV_HEADER: [&'static str; 8] = [
+        "timestamp",
+        "action",
+        "project",
+        "language",
+        "command",
+        "status",
+        "duration_ms",
+        "message",
+    ];
+
+    /// Writes `records` to `out` in this format.
+    pub fn write(
         &self,
-        last: usize,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
-        Ok(fs::read_to_string(&self.histfile)?
-            .lines()
-            .rev()
-            .take(last)
-            .map(|s| s.to_string())
-            .collect())
+        records: &[Record],
+        mut out: impl Write,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            Format::Text => {
+                for record in records {
+                    writeln!(out, "{}", record)?;
+                }
+            }
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut out, records)?;
+                writeln!(out)?;
+            }
+            Format::Csv => {
+                let mut csv = csv::Writer::from_writer(out);
+                csv.write_record(Self::CSV_HEADER)?;
+                for record in records {
+                    let fields = [
+                        // As in JSON.
+                        Some(record.timestamp.to_rfc3339_opts(
+                            SecondsFormat::AutoSi,
+                            true,
+                        )),
+                        Some(record.action.as_str().to_string()),
+                        record
+                            .project
+                            .as_ref()
+                            .map(|project| project.to_string_lossy().into()),
+                        record.language.clone(),
+                        record.command.clone(),
+                        record.status.map(|status| status.to_string()),
+                        record.duration_ms.map(|ms| ms.to_string()),
+                        record.message.clone(),
+                    ];
+                    csv.write_record(
+                        fields.iter().map(|f| f.as_deref().unwrap_or("")),
+                    )?;
+                }
+                csv.flush()?;
+            }
+        }
+
+        Ok(())
     }
+}
 
-    pub fn read_all(&self) -> Result<String, Box<dyn Error>> {
-        Ok(fs::read_to_string(&self.histfile)?)
+impl Query {
+    pub fn matches(&self, record: &Record) -> bool {
+        let project = record.project.as_deref();
+
+        self.action.is_none_or(|action| record.action == action)
+            && self.project.as_ref().is_none_or(|p| p.matches(project))
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.until.is_none_or(|until| record.timestamp < until)
+            && (!self.failed || record.status.is_some_and(|status| status != 0))
+            && self.grep.as_ref().is_none_or(|text| record.contains(text))
     }
+}
+
+impl Record {
+    /// Whether `text` is in the project, language, command or message,
+    /// ignoring ASCII case.
+    fn contains(&self, text: &str) -> bool {
+        let text = text.to_ascii_lowercase();
+        let project = self
+            .project
+            .as_ref()
+            .map(|project| project.to_string_lossy().into_owned());
 
-    fn timestamp(&self) -> String {
-        Local::now().format("%Y-%m-%d@%X: ").to_string()
+        [&project, &self.language, &self.command, &self.message]
+            .iter()
+            .filter_map(|field| field.as_ref())
+            .any(|field| field.to_ascii_lowercase().contains(&text))
     }
+}
 
-    fn delete_history_file(&self) -> Result<(), Box<dyn Error>> {
-        match fs::remove_file(&self.histfile) {
-            Ok(_) => Ok(()),
-            Err(e) => match e.kind() {
-                // Is it bad design to ignore this? I'm not sure
-                ErrorKind::NotFound => Ok(()),
-                _ => Err(format!(
-                    "Unable to delete history file: {}", e).into()
-                ),
+impl Retention {
+    /// The retention `settings` ask for, as of `now`.
+    pub fn from_settings(
+        settings: &HistorySettings,
+        now: DateTime<Local>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let since = match &settings.max_age {
+            Some(age) => match parse_time(age, now) {
+                Ok(since) => Some(since),
+                Err(e) => return Err(format!("Invalid max_age: {}", e).into()),
             },
+            None => None,
+        };
+
+        Ok(Retention {
+            max_entries: settings.max_entries,
+            since,
+        })
+    }
+
+    /// Whether everything is kept.
+    pub fn keeps_all(&self) -> bool {
+        self.max_entries.is_none() && self.since.is_none()
+    }
+
+    /// Splits `records`, oldest first, into the ones not kept and the ones
+    /// kept.
+    pub fn split(&self, records: Vec<Record>) -> (Vec<Record>, Vec<Record>) {
+        let first_kept = self
+            .max_entries
+            .map_or(0, |max| records.len().saturating_sub(max));
+
+        let mut pruned = Vec::new();
+        let mut kept = Vec::new();
+        for (i, record) in records.into_iter().enumerate() {
+            let too_old =
+                self.since.is_some_and(|since| record.timestamp < since);
+            if i < first_kept || too_old {
+                pruned.push(record);
+            } else {
+                kept.push(record);
+            }
+        }
+
+        (pruned, kept)
+    }
+}
+
+impl Project {
+    /// `arg` is either a project's name, or a path to a project or anywhere
+    /// inside one, e.g. `.`.
+    pub fn from_arg(arg: &str) -> Result<Self, Box<dyn Error>> {
+        let is_path = arg == "."
+            || arg == ".."
+            || arg.contains(std::path::MAIN_SEPARATOR);
+        if !is_path {
+            return Ok(Project::Name(arg.to_string()));
+        }
+
+        let path = match fs::canonicalize(arg) {
+            Ok(path) => path,
+            Err(e) => {
+                return Err(format!("Unable to find project {}: {}", arg, e)
+                    .into())
+            }
+        };
+        match SpaceworkFile::find_in_dir(&mut path.clone()) {
+            Ok(cfg) => Ok(Project::Path(cfg.root)),
+            Err(_) => Ok(Project::Path(path)),
+        }
+    }
+
+    fn matches(&self, project: Option<&Path>) -> bool {
+        match (self, project) {
+            (Project::Path(path), Some(project)) => path == project,
+            (Project::Name(name), Some(project)) => {
+                project.file_name().is_some_and(|n| n == name.as_str())
+            }
+            (_, None) => false,
+        }
+    }
+}
+
+/// Parses the times `--since` takes:
+///
+/// - `2021-08-10`: the start of that day
+/// - `2021-08-10 14:30`: that minute
+/// - `today` and `yesterday`: the start of the day
+/// - `30m`, `12h`, `2d`, `1w`: that long before `now`
+pub fn parse_time(
+    time: &str,
+    now: DateTime<Local>,
+) -> Result<DateTime<Local>, Box<dyn Error>> {
+    let time = time.trim();
+    let naive = match day(time, now) {
+        Some(day) => day.and_hms_opt(0, 0, 0),
+        None => NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").ok(),
+    };
+    if let Some(naive) = naive {
+        return local(naive, time);
+    }
+
+    let unit = time.len() - time.chars().last().map_or(0, char::len_utf8);
+    let ago = match (u32::from_str(&time[..unit]), &time[unit..]) {
+        (Ok(n), "m") => chrono::Duration::minutes(n.into()),
+        (Ok(n), "h") => chrono::Duration::hours(n.into()),
+        (Ok(n), "d") => chrono::Duration::days(n.into()),
+        (Ok(n), "w") => chrono::Duration::weeks(n.into()),
+        _ => {
+            return Err(format!(
+                "Unknown time `{}`. Use a date like `2021-08-10`, \
+                `2021-08-10 14:30`, `today`, `yesterday`, or how long ago \
+                like `30m`, `12h`, `2d` or `1w`",
+                time
+            )
+            .into())
+        }
+    };
+
+    Ok(now - ago)
+}
+
+/// Parses the times `--until` takes. They're the same as `parse_time`'s,
+/// except days mean their end, so `--until today` includes today.
+pub fn parse_until(
+    time: &str,
+    now: DateTime<Local>,
+) -> Result<DateTime<Local>, Box<dyn Error>> {
+    let time = time.trim();
+    let next = day(time, now).and_then(|day| day.succ_opt());
+    match next.and_then(|next| next.and_hms_opt(0, 0, 0)) {
+        Some(naive) => local(naive, time),
+        None => parse_time(time, now),
+    }
+}
+
+/// The day `time` is, if it's a whole one.
+fn day(time: &str, now: DateTime<Local>) -> Option<NaiveDate> {
+    match time {
+        "today" => Some(now.date_naive()),
+        "yesterday" => now.date_naive().pred_opt(),
+        _ => NaiveDate::parse_from_str(time, "%Y-%m-%d").ok(),
+    }
+}
+
+fn local(
+    naive: NaiveDateTime,
+    time: &str,
+) -> Result<DateTime<Local>, Box<dyn Error>> {
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(time) => Ok(time),
+        None => Err(format!("`{}` doesn't exist here", time).into()),
+    }
+}
+
+impl History {
+    const HISTFILE: &'static str = ".spacework_history";
+    const DATABASE: &'static str = ".spacework_history.db";
+    /// Touched whenever history is pruned.
+    const PRUNED: &'static str = ".spacework_history.pruned";
+    /// How often history is pruned as it's written.
+    const PRUNE_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let home_dir = home_dir()?;
+        let histfile = home_dir.join(Self::HISTFILE);
+
+        let settings = Settings::load()?.history;
+        let retention = Retention::from_settings(&settings, Local::now())?;
+        let store: Box<dyn Store> = match settings.backend {
+            Backend::Sqlite => {
+                let store = SqliteStore::open(&home_dir.join(Self::DATABASE))?;
+                let imported = store.import(&histfile)?;
+                if imported > 0 {
+                    eprintln!(
+                        "Imported {} history entries from {}",
+                        imported,
+                        histfile.display()
+                    );
+                }
+                Box::new(store)
+            }
+            Backend::File => Box::new(FileStore::open(histfile)?),
+        };
+
+        Ok(History { store, retention })
+    }
+
+    /// How much history the settings keep.
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    pub fn write(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        self.store.append(record)
+    }
+
+    /// Prunes history by the settings if they ask for it and it hasn't been
+    /// for a while, returning how many records were moved.
+    pub fn prune_if_due(&self) -> Result<usize, Box<dyn Error>> {
+        if self.retention.keeps_all() || !prune_due()? {
+            return Ok(0);
+        }
+
+        Ok(self.prune(&self.retention)?.0)
+    }
+
+    /// Moves the records `retention` doesn't keep into the archives for
+    /// the months they're from. Returns how many were moved, and the
+    /// archives.
+    pub fn prune(
+        &self,
+        retention: &Retention,
+    ) -> Result<(usize, Vec<PathBuf>), Box<dyn Error>> {
+        let home_dir = home_dir()?;
+        let mut archives = Vec::new();
+        let pruned = self.store.prune(retention, &mut |records| {
+            archives = archive_to(&home_dir, records)?;
+            Ok(())
+        })?;
+        fs::write(home_dir.join(Self::PRUNED), "")?;
+
+        Ok((pruned, archives))
+    }
+
+    /// The records `query` asks for, newest first.
+    pub fn query(&self, query: &Query) -> Result<Vec<Record>, Box<dyn Error>> {
+        self.store.query(query)
+    }
+
+    /// The newest record of each of the last `count` projects that still
+    /// exist, newest first.
+    pub fn recent_projects(
+        &self,
+        count: usize,
+    ) -> Result<Vec<Record>, Box<dyn Error>> {
+        Ok(self
+            .store
+            .projects()?
+            .into_iter()
+            .filter(|record| {
+                record.project.as_ref().is_some_and(|dir| dir.is_dir())
+            })
+            .take(count)
+            .collect())
+    }
+}
+
+/// Rewrites the lines of `~/.spacework_history` from before history was
+/// stored as records into records. Returns how many lines were changed.
+pub fn migrate() -> Result<usize, Box<dyn Error>> {
+    FileStore::open(home_dir()?.join(History::HISTFILE))?.migrate()
+}
+
+/// Whether it's been long enough since history was last pruned to prune it
+/// again.
+fn prune_due() -> Result<bool, Box<dyn Error>> {
+    let modified = match fs::metadata(home_dir()?.join(History::PRUNED)) {
+        Ok(metadata) => metadata.modified()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(modified
+        .elapsed()
+        .map_or(true, |elapsed| elapsed >= History::PRUNE_EVERY))
+}
+
+/// Adds `records` to the gzipped archives in `dir` for the months they're
+/// from, e.g. `.spacework_history.2021-08.gz`, one JSON record per line.
+/// Each addition is its own gzip member, which `zcat` and the like read as
+/// one file. Returns the archives added to.
+fn archive_to(
+    dir: &Path,
+    records: &[Record],
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut months = BTreeMap::new();
+    for record in records {
+        let path = dir.join(format!(
+            "{}.{}.gz",
+            History::HISTFILE,
+            record.timestamp.format("%Y-%m")
+        ));
+        let archive = months.entry(path).or_insert_with(|| {
+            GzEncoder::new(Vec::new(), Compression::default())
+        });
+        serde_json::to_writer(&mut *archive, record)?;
+        archive.write_all(b"\n")?;
+    }
+
+    // Every addition's ready before any is written, leaving as little as
+    // possible to go wrong with only some of them written.
+    let mut additions = Vec::new();
+    for (path, archive) in months {
+        additions.push((path, archive.finish()?));
+    }
+    for (path, addition) in additions.iter() {
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                file.write_all(addition)?;
+                file.sync_all()
+            });
+        if let Err(e) = written {
+            return Err(format!(
+                "Unable to write history archive {}: {}",
+                path.display(),
+                e
+            )
+            .into());
         }
     }
+
+    Ok(additions.into_iter().map(|(path, _)| path).collect())
 }
 
-pub fn delete_history_file() -> Result<(), Box<dyn Error>> {
-    History::new()?.delete_history_file()
+/// Deletes the history of every backend, and its archives.
+pub fn delete_history() -> Result<(), Box<dyn Error>> {
+    let home_dir = home_dir()?;
+    let histfile = home_dir.join(History::HISTFILE);
+    delete(&histfile)?;
+    delete(&file::lock_path(&histfile))?;
+    delete(&home_dir.join(History::DATABASE))?;
+    delete(&home_dir.join(History::PRUNED))?;
+
+    let archives = format!(
+        "{}.*.gz",
+        glob::Pattern::escape(&histfile.to_string_lossy())
+    );
+    for archive in glob::glob(&archives)? {
+        delete(&archive?)?;
+    }
+
+    Ok(())
+}
+
+fn delete(path: &Path) -> Result<(), Box<dyn Error>> {
+    match fs::remove_file(path) {
+        Ok(_) => Ok(()),
+        Err(e) => match e.kind() {
+            // Is it bad design to ignore this? I'm not sure
+            ErrorKind::NotFound => Ok(()),
+            _ => Err(format!(
+                "Unable to delete history file: {}", e).into()
+            ),
+        },
+    }
+}
+
+fn home_dir() -> Result<PathBuf, Box<dyn Error>> {
+    match env::var("HOME") {
+        Ok(home) => Ok(PathBuf::from(home)),
+        Err(e) => match e {
+            VarError::NotPresent => Err(
+                "HOME environment variable not found. \
+                Unable to find or create history file".into()
+            ),
+            VarError::NotUnicode(_) => Err(
+                "Unable to parse HOME environment variable: \
+                Invalid unicode".into()
+            ),
+        },
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::testing::TempDir;
 
     #[test]
-    fn todo() {
-        assert!(true);
+    fn records_shown_on_one_line() {
+        let built = Record {
+            project: Some(PathBuf::from("/home/marsha/spacework/cpp/hello")),
+            language: Some("C++".to_string()),
+            command: Some("g++ ./src/main.cpp".to_string()),
+            status: Some(1),
+            duration_ms: Some(1250),
+            ..Record::new(Action::Build)
+        };
+
+        assert!(built.to_string().ends_with(
+            "  build    /home/marsha/spacework/cpp/hello (C++)  \
+            $ g++ ./src/main.cpp  [exit 1, 1.25s]"
+        ));
+        assert!(Record::new(Action::New).to_string().ends_with("  new    "));
+    }
+
+    #[test]
+    fn records_exported() -> Result<(), Box<dyn Error>> {
+        let records = [
+            Record {
+                timestamp: Local
+                    .with_ymd_and_hms(2021, 8, 11, 14, 3, 12)
+                    .unwrap(),
+                project: Some(PathBuf::from("/ws/cpp/hello")),
+                command: Some("echo \"hi, there\"".to_string()),
+                status: Some(0),
+                duration_ms: Some(40),
+                ..Record::new(Action::Command)
+            },
+            Record::new(Action::Other),
+        ];
+        let export = |format: Format| -> Result<String, Box<dyn Error>> {
+            let mut out = Vec::new();
+            format.write(&records, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+
+        let json: Vec<Record> = serde_json::from_str(&export(Format::Json)?)?;
+        assert_eq!(json, records);
+
+        let csv = export(Format::Csv)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "timestamp,action,project,language,command,status,duration_ms,\
+            message"
+        );
+        assert_eq!(
+            lines[1],
+            format!(
+                "{},command,/ws/cpp/hello,,\"echo \"\"hi, there\"\"\",0,40,",
+                serde_json::to_value(records[0].timestamp)?
+                    .as_str()
+                    .unwrap_or_default()
+            )
+        );
+        assert!(lines[2].ends_with(",other,,,,,,"));
+
+        assert_eq!(export(Format::Text)?.lines().count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn finished_records_timed() {
+        let record = Record {
+            timestamp: Local::now() - chrono::Duration::milliseconds(1500),
+            ..Record::new(Action::Run)
+        }
+        .finish(3);
+
+        assert_eq!(record.status, Some(3));
+        assert!(record.duration_ms.is_some_and(|ms| ms >= 1500));
+    }
+
+    #[test]
+    fn queries_match_records() {
+        let project = PathBuf::from("/home/marsha/spacework/cpp/hello");
+        let failed_build = Record {
+            project: Some(project.clone()),
+            command: Some("g++ -Wall ./src/main.cpp".to_string()),
+            status: Some(2),
+            ..Record::new(Action::Build)
+        };
+        let hour = chrono::Duration::hours(1);
+        let query = Query {
+            action: Some(Action::Build),
+            project: Some(Project::Path(project)),
+            since: Some(failed_build.timestamp - hour),
+            until: Some(failed_build.timestamp + hour),
+            failed: true,
+            grep: Some("-WALL".to_string()),
+            limit: None,
+        };
+
+        assert!(query.matches(&failed_build));
+        assert!(Query {
+            project: Some(Project::Name("hello".to_string())),
+            ..query.clone()
+        }
+        .matches(&failed_build));
+        assert!(!Query {
+            project: Some(Project::Name("hell".to_string())),
+            ..query.clone()
+        }
+        .matches(&failed_build));
+        assert!(!Query {
+            until: Some(failed_build.timestamp),
+            ..query.clone()
+        }
+        .matches(&failed_build));
+        assert!(!Query {
+            grep: Some("clang".to_string()),
+            ..query.clone()
+        }
+        .matches(&failed_build));
+        assert!(!query.matches(&Record {
+            status: Some(0),
+            ..failed_build.clone()
+        }));
+        assert!(!query.matches(&Record {
+            project: None,
+            ..failed_build.clone()
+        }));
+        assert!(!query.matches(&Record::new(Action::Build)));
+        assert!(Query::default().matches(&Record::new(Action::Other)));
+    }
+
+    #[test]
+    fn retention_keeps_newest_records() -> Result<(), Box<dyn Error>> {
+        let now = Local::now();
+        let records: Vec<Record> = [30, 20, 10, 5, 1]
+            .iter()
+            .map(|&days| Record {
+                timestamp: now - chrono::Duration::days(days),
+                ..Record::new(Action::Build)
+            })
+            .collect();
+        let split = |retention: Retention| {
+            let (pruned, kept) = retention.split(records.clone());
+            (pruned.len(), kept.len())
+        };
+
+        assert_eq!(split(Retention::default()), (0, 5));
+        let settings = HistorySettings {
+            max_entries: Some(4),
+            max_age: Some("2w".to_string()),
+            ..HistorySettings::default()
+        };
+        assert_eq!(split(Retention::from_settings(&settings, now)?), (2, 3));
+        let settings = HistorySettings {
+            max_entries: Some(2),
+            ..HistorySettings::default()
+        };
+        assert_eq!(split(Retention::from_settings(&settings, now)?), (3, 2));
+        let settings = HistorySettings {
+            max_age: Some("forever".to_string()),
+            ..HistorySettings::default()
+        };
+        assert!(Retention::from_settings(&settings, now).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn archives_added_to() -> Result<(), Box<dyn Error>> {
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let dir = TempDir::new("archive")?;
+        let at = |m, d| Record {
+            timestamp: Local.with_ymd_and_hms(2021, m, d, 12, 0, 0).unwrap(),
+            ..Record::new(Action::Build)
+        };
+        let records = [at(7, 30), at(7, 31), at(8, 1), at(8, 2)];
+
+        let first = archive_to(&dir, &records[..3])?;
+        let second = archive_to(&dir, &records[3..])?;
+        let read = |month| -> Result<Vec<Record>, Box<dyn Error>> {
+            let path = dir.join(format!(".spacework_history.{}.gz", month));
+            let mut contents = String::new();
+            MultiGzDecoder::new(fs::File::open(path)?)
+                .read_to_string(&mut contents)?;
+            Ok(contents
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?)
+        };
+        let (july, august) = (read("2021-07"), read("2021-08"));
+
+        assert_eq!(first, [
+            dir.join(".spacework_history.2021-07.gz"),
+            dir.join(".spacework_history.2021-08.gz"),
+        ]);
+        assert_eq!(second[..], first[1..]);
+        assert_eq!(july?, records[..2]);
+        assert_eq!(august?, records[2..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn times_parsed() -> Result<(), Box<dyn Error>> {
+        let now = Local.with_ymd_and_hms(2021, 8, 12, 15, 45, 0).unwrap();
+        let at = |y, m, d, h, min| {
+            Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+        };
+
+        assert_eq!(parse_time("2021-08-10", now)?, at(2021, 8, 10, 0, 0));
+        assert_eq!(
+            parse_time("2021-08-10 14:30", now)?,
+            at(2021, 8, 10, 14, 30)
+        );
+        assert_eq!(parse_time("today", now)?, at(2021, 8, 12, 0, 0));
+        assert_eq!(parse_time("yesterday", now)?, at(2021, 8, 11, 0, 0));
+        assert_eq!(parse_time("2d", now)?, at(2021, 8, 10, 15, 45));
+        assert_eq!(parse_time("1w", now)?, at(2021, 8, 5, 15, 45));
+        assert_eq!(parse_time("90m", now)?, at(2021, 8, 12, 14, 15));
+
+        assert!(parse_time("2x", now).is_err());
+        assert!(parse_time("d", now).is_err());
+        assert!(parse_time("", now).is_err());
+        assert!(parse_time("last tuesday", now).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn until_includes_whole_days() -> Result<(), Box<dyn Error>> {
+        let now = Local.with_ymd_and_hms(2021, 8, 12, 15, 45, 0).unwrap();
+        let at = |y, m, d, h, min| {
+            Local.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+        };
+
+        assert_eq!(parse_until("2021-08-10", now)?, at(2021, 8, 11, 0, 0));
+        assert_eq!(parse_until("2021-12-31", now)?, at(2022, 1, 1, 0, 0));
+        assert_eq!(parse_until("today", now)?, at(2021, 8, 13, 0, 0));
+        assert_eq!(parse_until("yesterday", now)?, at(2021, 8, 12, 0, 0));
+        assert_eq!(
+            parse_until("2021-08-10 14:30", now)?,
+            at(2021, 8, 10, 14, 30)
+        );
+        assert_eq!(parse_until("2d", now)?, at(2021, 8, 10, 15, 45));
+        assert!(parse_until("last tuesday", now).is_err());
+
+        // Everything from today is shown, not nothing.
+        let query = Query {
+            until: Some(parse_until("today", now)?),
+            ..Query::default()
+        };
+        assert!(query.matches(&Record {
+            timestamp: now,
+            ..Record::new(Action::Build)
+        }));
+
+        Ok(())
     }
 }
@@ -0,0 +1,514 @@
+use super::{Action, Archive, Query, Record, Retention, Store};
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use chrono::prelude::*;
+use fs2::FileExt;
+
+/// History kept in a text file, one JSON record per line.
+///
+/// Several spacework processes can use it at once: everything that reads it
+/// holds a shared lock on a lock file next to it, and everything that
+/// changes it an exclusive one. The lock file is never replaced, unlike the
+/// history file when it's rewritten.
+pub struct FileStore {
+    histfile: PathBuf,
+}
+
+impl FileStore {
+    /// The history in `histfile`, which is created if it doesn't exist.
+    pub fn open(histfile: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let store = Self::at(histfile);
+        let _lock = store.lock(true)?;
+        store.create_history_file()?;
+
+        Ok(store)
+    }
+
+    /// The history in `histfile`, without creating it.
+    pub fn at(histfile: PathBuf) -> Self {
+        FileStore { histfile }
+    }
+
+    fn create_history_file(&self) -> Result<(), Box<dyn Error>> {
+        // Only created if it doesn't exist, in one step, so two processes
+        // can't both create it.
+        let created = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.histfile);
+        match created {
+            Ok(_) => {
+                println!(
+                    "Created spacework history file: {}",
+                    self.histfile.display()
+                );
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(()),
+            Err(e) => Err(
+                format!("Unable to create history file: {}", e).into()
+            ),
+        }
+    }
+
+    fn file(&self) -> Result<File, Box<dyn Error>> {
+        match OpenOptions::new().append(true).open(&self.histfile) {
+            Ok(file) => Ok(file),
+            Err(e) => Err(
+                format!("Unable to open history file: {}", e).into()
+            ),
+        }
+    }
+
+    /// Locks the history, exclusively to change it or shared to read it,
+    /// until the returned file is dropped.
+    fn lock(&self, exclusive: bool) -> Result<File, Box<dyn Error>> {
+        let path = lock_path(&self.histfile);
+        let file = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(format!(
+                    "Unable to open history lock file {}: {}",
+                    path.display(),
+                    e
+                )
+                .into())
+            }
+        };
+
+        let locked = if exclusive {
+            file.lock_exclusive()
+        } else {
+            file.lock_shared()
+        };
+        match locked {
+            Ok(_) => Ok(file),
+            Err(e) => Err(format!("Unable to lock history file: {}", e).into()),
+        }
+    }
+
+    /// Every record, oldest first.
+    pub fn read_all(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        let _lock = self.lock(false)?;
+        self.read_all_unlocked()
+    }
+
+    fn read_all_unlocked(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        fs::read_to_string(&self.histfile)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| match parse(line) {
+                Ok(record) => Ok(record),
+                Err(e) => Err(format!(
+                    "Unable to read line {} of history file: {}",
+                    i + 1,
+                    e
+                )
+                .into()),
+            })
+            .collect()
+    }
+
+    /// Calls `visit` with each record, newest first, until it returns
+    /// false. The file is read from the end, so only as much of it as is
+    /// needed is read.
+    fn read_back(
+        &self,
+        mut visit: impl FnMut(Record) -> bool,
+    ) -> Result<(), Box<dyn Error>> {
+        const CHUNK: u64 = 64 * 1024;
+
+        let _lock = self.lock(false)?;
+        let mut file = File::open(&self.histfile)?;
+        let mut pos = file.seek(SeekFrom::End(0))?;
+        // The end of a line whose start hasn't been read yet.
+        let mut rest = Vec::new();
+        while pos > 0 {
+            let len = CHUNK.min(pos);
+            pos -= len;
+            let mut chunk = vec![0; len as usize];
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut chunk)?;
+            chunk.append(&mut rest);
+
+            let mut lines = chunk.split(|&byte| byte == b'\n');
+            // Unless this is the start of the file, the first line might be
+            // missing its start.
+            if pos > 0 {
+                rest = lines.next().unwrap_or_default().to_vec();
+            }
+            for line in lines.rev() {
+                let line = str::from_utf8(line)?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = match parse(line) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        return Err(format!(
+                            "Unable to read history file: {}",
+                            e
+                        )
+                        .into())
+                    }
+                };
+                if !visit(record) {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces every record with `records`.
+    fn rewrite(&self, records: &[Record]) -> Result<(), Box<dyn Error>> {
+        let tmp = self.write_tmp(records)?;
+        fs::rename(&tmp, &self.histfile)?;
+
+        Ok(())
+    }
+
+    /// Writes `records` next to the history file, to be moved over it. The
+    /// history isn't lost that way if anything goes wrong halfway.
+    fn write_tmp(&self, records: &[Record]) -> Result<PathBuf, Box<dyn Error>> {
+        let mut contents = String::new();
+        for record in records {
+            contents.push_str(&serde_json::to_string(record)?);
+            contents.push('\n');
+        }
+
+        let tmp = self.histfile.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+
+        Ok(tmp)
+    }
+
+    /// Rewrites lines from before history was stored as records into
+    /// records. Returns how many lines were changed.
+    pub fn migrate(&self) -> Result<usize, Box<dyn Error>> {
+        let _lock = self.lock(true)?;
+        let contents = fs::read_to_string(&self.histfile)?;
+        let old = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('{'))
+            .count();
+        if old == 0 {
+            return Ok(0);
+        }
+
+        self.rewrite(&self.read_all_unlocked()?)?;
+
+        Ok(old)
+    }
+}
+
+impl Store for FileStore {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        // The whole line in one write, which no other spacework process
+        // writes in the middle of while the lock's held.
+        let line = format!("{}\n", serde_json::to_string(record)?);
+        let _lock = self.lock(true)?;
+        match self.file()?.write_all(line.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(
+                format!("Unable to write to history file: {}", e).into()
+            ),
+        }
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<Record>, Box<dyn Error>> {
+        let mut records = Vec::new();
+        self.read_back(|record| {
+            if query.matches(&record) {
+                records.push(record);
+            }
+            query.limit.is_none_or(|limit| records.len() < limit)
+        })?;
+
+        Ok(records)
+    }
+
+    fn projects(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        let mut seen = HashSet::new();
+        let mut records = Vec::new();
+        self.read_back(|record| {
+            if let Some(project) = &record.project {
+                if seen.insert(project.clone()) {
+                    records.push(record);
+                }
+            }
+            true
+        })?;
+
+        Ok(records)
+    }
+
+    fn prune(
+        &self,
+        retention: &Retention,
+        archive: &mut Archive,
+    ) -> Result<usize, Box<dyn Error>> {
+        let _lock = self.lock(true)?;
+        let (pruned, kept) = retention.split(self.read_all_unlocked()?);
+        if pruned.is_empty() {
+            return Ok(0);
+        }
+
+        // Archived once the new history's ready, and only then moved into
+        // place, so neither can be left changed without the other.
+        let tmp = self.write_tmp(&kept)?;
+        if let Err(e) = archive(&pruned) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        fs::rename(&tmp, &self.histfile)?;
+
+        Ok(pruned.len())
+    }
+}
+
+/// The lock file of `histfile`.
+pub fn lock_path(histfile: &Path) -> PathBuf {
+    let mut path = histfile.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// Reads a line of the history file, either a JSON record or a
+/// `<timestamp>: text` line from before records existed.
+fn parse(line: &str) -> Result<Record, Box<dyn Error>> {
+    if line.starts_with('{') {
+        return Ok(serde_json::from_str(line)?);
+    }
+
+    let (timestamp, text) = match line.split_once(": ") {
+        Some(parts) => parts,
+        None => return Err(format!("Unknown history line `{}`", line).into()),
+    };
+    let timestamp =
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d@%H:%M:%S")?;
+    let timestamp = match Local.from_local_datetime(&timestamp).earliest() {
+        Some(timestamp) => timestamp,
+        None => return Err(format!("Invalid time in `{}`", line).into()),
+    };
+    let text = text.trim();
+
+    let mut record = Record {
+        timestamp,
+        ..Record::new(Action::Other)
+    };
+    match text.strip_prefix("Created project directory: ") {
+        Some(project) => {
+            record.action = Action::New;
+            record.project = Some(PathBuf::from(project));
+        }
+        None => record.message = Some(text.to_string()),
+    }
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::thread;
+
+    /// A history file in `dir` to start with `contents`.
+    fn store(dir: &Path, contents: &str) -> Result<FileStore, Box<dyn Error>> {
+        let histfile = dir.join(".spacework_history");
+        fs::write(&histfile, contents)?;
+
+        Ok(FileStore::at(histfile))
+    }
+
+    #[test]
+    fn records_written_and_queried() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("records")?;
+        let store = store(&dir, "")?;
+        let failed = Record {
+            command: Some("g++ ./src/main.cpp".to_string()),
+            status: Some(1),
+            duration_ms: Some(1250),
+            ..Record::new(Action::Build)
+        };
+        store.append(&Record::new(Action::New))?;
+        store.append(&failed)?;
+        store.append(&Record {
+            status: Some(0),
+            ..Record::new(Action::Build)
+        })?;
+
+        let all = store.read_all()?;
+        let last = store.query(&Query {
+            limit: Some(1),
+            ..Query::default()
+        })?;
+        let failed_builds = store.query(&Query {
+            action: Some(Action::Build),
+            failed: true,
+            ..Query::default()
+        })?;
+
+        assert_eq!(all.len(), 3);
+        assert_eq!(last[..], all[2..]);
+        assert_eq!(failed_builds, [failed]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn newest_records_read_from_the_end() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("tail")?;
+        let store = store(&dir, "2021-08-11@13:59:08:  Hello!\n")?;
+        // Enough to take a few reads from the end to get through.
+        for i in 0..300 {
+            store.append(&Record {
+                message: Some(format!("{} {}", i, "x".repeat(1000))),
+                ..Record::new(Action::Other)
+            })?;
+        }
+
+        let all = store.read_all()?;
+        let newest = store.query(&Query::default())?;
+        let last = store.query(&Query {
+            limit: Some(2),
+            ..Query::default()
+        })?;
+
+        assert_eq!(newest.len(), 301);
+        assert!(newest.iter().eq(all.iter().rev()));
+        assert_eq!(last[..], newest[..2]);
+
+        let retention = Retention {
+            max_entries: Some(100),
+            ..Retention::default()
+        };
+        let mut archived = Vec::new();
+        let pruned = store.prune(&retention, &mut |records| {
+            archived.extend_from_slice(records);
+            Ok(())
+        })?;
+        let kept = store.read_all()?;
+
+        // Nothing's removed if archiving fails.
+        let failed = store.prune(
+            &Retention {
+                max_entries: Some(1),
+                ..Retention::default()
+            },
+            &mut |_| Err("disk full".into()),
+        );
+        let after_failure = store.read_all()?;
+        let tmp = store.histfile.with_extension("tmp");
+
+        assert!(failed.is_err());
+        assert_eq!(after_failure, kept);
+        assert!(!tmp.exists());
+        assert_eq!(pruned, 201);
+        assert_eq!(archived[..], all[..201]);
+        assert_eq!(kept[..], all[201..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn appends_from_many_threads_kept_whole() -> Result<(), Box<dyn Error>> {
+        const THREADS: usize = 16;
+        const RECORDS: usize = 50;
+
+        let dir = TempDir::new("hammer")?;
+        let histfile = dir.join(".spacework_history");
+
+        // Each thread opens the history itself, as separate spacework
+        // processes would, racing to create it.
+        let threads: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let histfile = histfile.clone();
+                thread::spawn(move || {
+                    let store = FileStore::open(histfile).unwrap();
+                    for i in 0..RECORDS {
+                        store
+                            .append(&Record {
+                                // Long enough to need more than one write
+                                // if it wasn't written all at once.
+                                message: Some(format!(
+                                    "{} {} {}",
+                                    thread,
+                                    i,
+                                    "x".repeat(8192)
+                                )),
+                                ..Record::new(Action::Other)
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let records = FileStore::at(histfile).read_all()?;
+
+        assert_eq!(records.len(), THREADS * RECORDS);
+        for thread in 0..THREADS {
+            let written: Vec<usize> = records
+                .iter()
+                .filter_map(|record| {
+                    let mut words = record.message.as_ref()?.split(' ');
+                    let from: usize = words.next()?.parse().ok()?;
+                    let i = words.next()?.parse().ok()?;
+                    (from == thread).then_some(i)
+                })
+                .collect();
+            assert_eq!(written, (0..RECORDS).collect::<Vec<_>>());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn old_lines_read_and_migrated() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("migrate")?;
+        let store = store(
+            &dir,
+            "2021-08-11@13:59:08:  Hello hello, world!\n\
+            2021-08-11@13:59:09:  Created project directory: /tmp/hello\n",
+        )?;
+        store.append(&Record::new(Action::Run))?;
+
+        let before = store.read_all()?;
+        assert_eq!(before[0].action, Action::Other);
+        assert_eq!(before[0].message.as_deref(), Some("Hello hello, world!"));
+        assert_eq!(
+            before[0].timestamp.format("%F %T").to_string(),
+            "2021-08-11 13:59:08"
+        );
+        assert_eq!(before[1].action, Action::New);
+        assert_eq!(before[1].project, Some(PathBuf::from("/tmp/hello")));
+
+        assert_eq!(store.migrate()?, 2);
+        assert_eq!(store.migrate()?, 0);
+        let after = store.read_all()?;
+        let contents = fs::read_to_string(&store.histfile)?;
+
+        assert_eq!(after, before);
+        assert!(contents.lines().all(|line| line.starts_with('{')));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,570 @@
+use super::file::FileStore;
+use super::{Archive, Project, Query, Record, Retention, Store};
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+
+/// History kept in an SQLite database, so it can be searched without
+/// reading all of it.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY,
+        -- Milliseconds since the Unix epoch, for sorting and comparing.
+        time INTEGER NOT NULL,
+        -- RFC 3339, which keeps the UTC offset the record was made with.
+        timestamp TEXT NOT NULL,
+        action TEXT NOT NULL,
+        project TEXT,
+        language TEXT,
+        command TEXT,
+        status INTEGER,
+        duration_ms INTEGER,
+        message TEXT
+    );
+    CREATE INDEX IF NOT EXISTS history_time ON history (time);
+    CREATE INDEX IF NOT EXISTS history_project ON history (project, time);
+
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const COLUMNS: &str = "timestamp, action, project, language, command, \
+    status, duration_ms, message";
+
+impl SqliteStore {
+    /// The database at `path`, which is created if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let conn = match Connection::open(path) {
+            Ok(conn) => conn,
+            Err(e) => {
+                return Err(format!(
+                    "Unable to open history database {}: {}",
+                    path.display(),
+                    e
+                )
+                .into())
+            }
+        };
+        // Other spacework processes may be writing to it.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(SqliteStore { conn })
+    }
+
+    /// The records `sql` selects `COLUMNS` of.
+    fn records(
+        &self,
+        sql: &str,
+        values: Vec<Value>,
+    ) -> Result<Vec<Record>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map(values, |row| {
+            Ok(Row {
+                timestamp: row.get(0)?,
+                action: row.get(1)?,
+                project: row.get(2)?,
+                language: row.get(3)?,
+                command: row.get(4)?,
+                status: row.get(5)?,
+                duration_ms: row.get(6)?,
+                message: row.get(7)?,
+            })
+        })?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            records.push(row?.into_record()?);
+        }
+
+        Ok(records)
+    }
+
+    /// Copies the records in the text history file `histfile` into the
+    /// database, unless that's been done before. Returns how many records
+    /// were copied.
+    pub fn import(&self, histfile: &Path) -> Result<usize, Box<dyn Error>> {
+        if imported(&self.conn)? {
+            return Ok(0);
+        }
+
+        // Immediate and checked again, so two spacework processes can't
+        // both import.
+        let tx = Transaction::new_unchecked(
+            &self.conn,
+            TransactionBehavior::Immediate,
+        )?;
+        if imported(&tx)? {
+            return Ok(0);
+        }
+
+        let records = if histfile.exists() {
+            FileStore::at(histfile.to_path_buf()).read_all()?
+        } else {
+            Vec::new()
+        };
+        for record in records.iter() {
+            insert(&tx, record)?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('imported', ?)",
+            params![histfile.to_string_lossy()],
+        )?;
+        tx.commit()?;
+
+        Ok(records.len())
+    }
+}
+
+impl Store for SqliteStore {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        match insert(&self.conn, record) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(
+                format!("Unable to write to history database: {}", e).into()
+            ),
+        }
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<Record>, Box<dyn Error>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(action) = query.action {
+            conditions.push("action = ?");
+            values.push(Value::Text(action.as_str().to_string()));
+        }
+        match &query.project {
+            Some(Project::Path(path)) => {
+                conditions.push("project = ?");
+                values.push(Value::Text(path.to_string_lossy().into_owned()));
+            }
+            Some(Project::Name(name)) => {
+                conditions.push("project LIKE ? ESCAPE '\\'");
+                values.push(Value::Text(format!("%/{}", escape_like(name))));
+            }
+            None => {}
+        }
+        if let Some(since) = query.since {
+            conditions.push("time >= ?");
+            values.push(Value::Integer(since.timestamp_millis()));
+        }
+        if let Some(until) = query.until {
+            conditions.push("time < ?");
+            values.push(Value::Integer(until.timestamp_millis()));
+        }
+        if query.failed {
+            conditions.push("status <> 0");
+        }
+        if let Some(text) = &query.grep {
+            conditions.push(
+                "(instr(lower(project), ?) OR instr(lower(language), ?) \
+                OR instr(lower(command), ?) OR instr(lower(message), ?))",
+            );
+            for _ in 0..4 {
+                values.push(Value::Text(text.to_ascii_lowercase()));
+            }
+        }
+
+        let mut sql = format!("SELECT {} FROM history", COLUMNS);
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY time DESC, id DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(" LIMIT ?");
+            values.push(Value::Integer(limit as i64));
+        }
+
+        self.records(&sql, values)
+    }
+
+    fn projects(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        // Records with no newer one for the same project.
+        let sql = format!(
+            "SELECT {} FROM history h WHERE project IS NOT NULL \
+            AND NOT EXISTS (SELECT 1 FROM history n \
+                WHERE n.project = h.project \
+                AND (n.time > h.time OR (n.time = h.time AND n.id > h.id))) \
+            ORDER BY time DESC, id DESC",
+            COLUMNS
+        );
+
+        self.records(&sql, Vec::new())
+    }
+
+    fn prune(
+        &self,
+        retention: &Retention,
+        archive: &mut Archive,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(since) = retention.since {
+            conditions.push("time < ?");
+            values.push(Value::Integer(since.timestamp_millis()));
+        }
+        if let Some(max) = retention.max_entries {
+            conditions.push(
+                "id NOT IN (SELECT id FROM history \
+                ORDER BY time DESC, id DESC LIMIT ?)",
+            );
+            values.push(Value::Integer(max as i64));
+        }
+        if conditions.is_empty() {
+            return Ok(0);
+        }
+        let conditions = conditions.join(" OR ");
+
+        // Immediate, so nothing's added between reading what's pruned and
+        // removing it.
+        let tx = Transaction::new_unchecked(
+            &self.conn,
+            TransactionBehavior::Immediate,
+        )?;
+        let pruned = self.records(
+            &format!(
+                "SELECT {} FROM history WHERE {} ORDER BY time, id",
+                COLUMNS, conditions
+            ),
+            values.clone(),
+        )?;
+        if pruned.is_empty() {
+            return Ok(0);
+        }
+
+        // Removed before they're archived, but only committed after, so a
+        // failure either side leaves them in the history and not archived.
+        tx.execute(
+            &format!("DELETE FROM history WHERE {}", conditions),
+            values,
+        )?;
+        archive(&pruned)?;
+        tx.commit()?;
+
+        Ok(pruned.len())
+    }
+}
+
+/// A record as it's stored, before its columns are parsed.
+struct Row {
+    timestamp: String,
+    action: String,
+    project: Option<String>,
+    language: Option<String>,
+    command: Option<String>,
+    status: Option<i32>,
+    duration_ms: Option<i64>,
+    message: Option<String>,
+}
+
+impl Row {
+    fn into_record(self) -> Result<Record, Box<dyn Error>> {
+        Ok(Record {
+            timestamp: DateTime::parse_from_rfc3339(&self.timestamp)?
+                .with_timezone(&Local),
+            action: self.action.parse()?,
+            project: self.project.map(PathBuf::from),
+            language: self.language,
+            command: self.command,
+            status: self.status,
+            duration_ms: self.duration_ms.map(|ms| ms as u64),
+            message: self.message,
+        })
+    }
+}
+
+/// `text` with the characters `LIKE` treats specially escaped by `\\`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn imported(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM meta WHERE key = 'imported')",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+fn insert(conn: &Connection, record: &Record) -> rusqlite::Result<usize> {
+    conn.execute(
+        &format!(
+            "INSERT INTO history (time, {}) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            COLUMNS
+        ),
+        params![
+            record.timestamp.timestamp_millis(),
+            record.timestamp.to_rfc3339(),
+            record.action.as_str(),
+            record
+                .project
+                .as_ref()
+                .map(|project| project.to_string_lossy().into_owned()),
+            record.language,
+            record.command,
+            record.status,
+            record.duration_ms.map(|ms| ms as i64),
+            record.message,
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Action;
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn failed_builds_of_a_project_queried() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("sqlite")?;
+        let db = dir.join("query.db");
+        let store = SqliteStore::open(&db)?;
+
+        let hello = PathBuf::from("/home/marsha/spacework/cpp/hello_socks");
+        let build = |project: &Path, status: i32| Record {
+            project: Some(project.to_path_buf()),
+            language: Some("C++".to_string()),
+            command: Some("g++ ./src/main.cpp".to_string()),
+            status: Some(status),
+            duration_ms: Some(40),
+            ..Record::new(Action::Build)
+        };
+        for status in [1, 0, 1, 2] {
+            store.append(&build(&hello, status))?;
+        }
+        store.append(&build(Path::new("/tmp/other"), 1))?;
+        store.append(&Record::new(Action::New))?;
+
+        let failed = store.query(&Query {
+            action: Some(Action::Build),
+            project: Some(Project::Path(hello.clone())),
+            failed: true,
+            limit: Some(2),
+            ..Query::default()
+        })?;
+        let all = store.query(&Query::default())?;
+
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0], build(&hello, 2).with_time(&failed[0]));
+        assert_eq!(failed[1].status, Some(1));
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[0].action, Action::New);
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_agree_with_file_store() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("sqlite")?;
+        let db = dir.join("filters.db");
+        let histfile = dir.join("filters_history");
+        let sqlite = SqliteStore::open(&db)?;
+        fs::write(&histfile, "")?;
+        let file = FileStore::at(histfile.clone());
+
+        let now = Local::now();
+        let record = |days: i64, project: &str, command: &str, status| Record {
+            timestamp: now - chrono::Duration::days(days),
+            project: Some(PathBuf::from(project)),
+            command: Some(command.to_string()),
+            status: Some(status),
+            ..Record::new(Action::Build)
+        };
+        for record in [
+            record(9, "/ws/cpp/hello_socks", "g++ -O2 main.cpp", 0),
+            record(5, "/ws/cpp/helloXsocks", "g++ -Wall main.cpp", 1),
+            record(3, "/ws/c/hello_socks", "cc 100%.c", 1),
+            record(1, "/ws/cpp/hello_socks", "G++ -O2 main.cpp", 0),
+        ] {
+            sqlite.append(&record)?;
+            file.append(&record)?;
+        }
+
+        let name = |name: &str| Some(Project::Name(name.to_string()));
+        let queries = [
+            Query {
+                project: name("hello_socks"),
+                ..Query::default()
+            },
+            Query {
+                project: name("socks"),
+                ..Query::default()
+            },
+            Query {
+                since: Some(now - chrono::Duration::days(4)),
+                ..Query::default()
+            },
+            Query {
+                since: Some(now - chrono::Duration::days(6)),
+                until: Some(now - chrono::Duration::days(2)),
+                failed: true,
+                ..Query::default()
+            },
+            Query {
+                grep: Some("g++ -o2".to_string()),
+                ..Query::default()
+            },
+            Query {
+                grep: Some("100%".to_string()),
+                ..Query::default()
+            },
+        ];
+        let mut counts = Vec::new();
+        for query in queries.iter() {
+            let expected = file.query(query)?;
+            assert_eq!(sqlite.query(query)?, expected, "{:?}", query);
+            counts.push(expected.len());
+        }
+
+        assert_eq!(counts, [3, 0, 2, 2, 2, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn projects_agree_with_file_store() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("sqlite")?;
+        let db = dir.join("projects.db");
+        let histfile = dir.join("projects_history");
+        let sqlite = SqliteStore::open(&db)?;
+        fs::write(&histfile, "")?;
+        let file = FileStore::at(histfile.clone());
+
+        let now = Local::now();
+        let record = |minutes: i64, action, project: Option<&str>| Record {
+            timestamp: now - chrono::Duration::minutes(minutes),
+            project: project.map(PathBuf::from),
+            ..Record::new(action)
+        };
+        for record in [
+            record(9, Action::New, Some("/ws/cpp/hello")),
+            record(8, Action::New, Some("/ws/rust/socks")),
+            record(7, Action::Build, Some("/ws/cpp/hello")),
+            record(6, Action::Other, None),
+            record(5, Action::Run, Some("/ws/cpp/hello")),
+            record(5, Action::Command, Some("/ws/cpp/hello")),
+        ] {
+            sqlite.append(&record)?;
+            file.append(&record)?;
+        }
+
+        let projects = file.projects()?;
+        assert_eq!(sqlite.projects()?, projects);
+
+        let actions: Vec<Action> = projects.iter().map(|p| p.action).collect();
+        assert_eq!(actions, [Action::Command, Action::New]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pruning_agrees_with_file_store() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("sqlite")?;
+        let db = dir.join("prune.db");
+        let histfile = dir.join("prune_history");
+        let sqlite = SqliteStore::open(&db)?;
+        fs::write(&histfile, "")?;
+        let file = FileStore::at(histfile.clone());
+
+        let now = Local::now();
+        for days in [40, 30, 20, 10, 5, 1] {
+            let record = Record {
+                timestamp: now - chrono::Duration::days(days),
+                ..Record::new(Action::Build)
+            };
+            sqlite.append(&record)?;
+            file.append(&record)?;
+        }
+
+        let prune = |store: &dyn Store, retention: Retention| {
+            let mut archived = Vec::new();
+            let pruned = store.prune(&retention, &mut |records| {
+                archived.extend_from_slice(records);
+                Ok(())
+            });
+            let kept = store.query(&Query::default());
+            (pruned.unwrap(), archived, kept.unwrap())
+        };
+        let retentions = [
+            Retention {
+                since: Some(now - chrono::Duration::days(35)),
+                ..Retention::default()
+            },
+            Retention {
+                max_entries: Some(3),
+                since: Some(now - chrono::Duration::days(15)),
+            },
+        ];
+        for retention in retentions {
+            let (pruned, archived, kept) = prune(&file, retention);
+            assert_eq!(prune(&sqlite, retention), (pruned, archived, kept));
+        }
+
+        // Nothing's removed if archiving fails.
+        let failed = sqlite.prune(
+            &Retention {
+                max_entries: Some(1),
+                ..Retention::default()
+            },
+            &mut |_| Err("disk full".into()),
+        );
+        let kept = sqlite.query(&Query::default())?;
+
+        assert!(failed.is_err());
+        assert_eq!(kept.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn text_history_imported_once() -> Result<(), Box<dyn Error>> {
+        let dir = TempDir::new("sqlite")?;
+        let db = dir.join("import.db");
+        let histfile = dir.join("import_history");
+        fs::write(
+            &histfile,
+            "2021-08-11@13:59:09:  Created project directory: /tmp/hello\n",
+        )?;
+        FileStore::at(histfile.clone()).append(&Record::new(Action::Run))?;
+
+        let store = SqliteStore::open(&db)?;
+        let first = store.import(&histfile)?;
+        let second = SqliteStore::open(&db)?.import(&histfile)?;
+        let records = store.query(&Query::default())?;
+
+        assert_eq!((first, second), (2, 0));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, Action::Run);
+        assert_eq!(records[1].project, Some(PathBuf::from("/tmp/hello")));
+
+        Ok(())
+    }
+
+    impl Record {
+        /// `self` with `other`'s time, which differs between records made
+        /// in the test.
+        fn with_time(self, other: &Record) -> Record {
+            Record {
+                timestamp: other.timestamp,
+                ..self
+            }
+        }
+    }
+}
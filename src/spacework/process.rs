@@ -0,0 +1,443 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// spacework's exit codes. These are a stable contract scripts can rely on:
+///
+/// - `0`: Everything went well.
+/// - `1`..=`123`: A build, `spacework run` program or runfile command exited
+///   with this code, which spacework exits with too.
+/// - `124`: A runfile command ran past its `timeout` and was killed, the
+///   same way GNU `timeout` reports it.
+/// - `125`: spacework itself ran into a problem, e.g. invalid command line
+///   options, an unreadable `spacework.toml` or a command that couldn't be
+///   started.
+/// - `128 + N`: A command was killed by signal `N`, the same way shells
+///   report it.
+///
+/// Commands exiting with `124` or above are passed along as-is, so they
+/// can't be told apart from the codes above.
+pub mod exit {
+    use std::process::ExitStatus;
+
+    pub const SUCCESS: i32 = 0;
+    pub const TIMED_OUT: i32 = 124;
+    pub const INTERNAL_ERROR: i32 = 125;
+    pub const SIGNAL_BASE: i32 = 128;
+
+    /// The exit code to pass along for a command that exited with `status`.
+    pub fn code(status: ExitStatus) -> i32 {
+        if let Some(code) = status.code() {
+            return code;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            if let Some(signal) = status.signal() {
+                return SIGNAL_BASE + signal;
+            }
+        }
+
+        INTERNAL_ERROR
+    }
+}
+
+/// How a command run by `run_with_timeout` ended.
+#[derive(Debug)]
+pub struct Finished {
+    pub output: Output,
+    /// Whether it was killed for running too long.
+    pub timed_out: bool,
+}
+
+/// Runs `cmd`, copying its stdout and stderr to ours as soon as anything is
+/// written to them, while also keeping a copy of both to return.
+pub fn run(cmd: &mut Command) -> io::Result<Output> {
+    Ok(run_with_timeout(cmd, None)?.output)
+}
+
+/// How long output is still read for after a command with a timeout has
+/// finished and the rest of its process group's been killed. Any process
+/// that got away, e.g. by starting its own session, could keep the pipes
+/// open forever.
+const READ_AFTER_KILL: Duration = Duration::from_millis(500);
+
+/// Like `run`, but if `cmd` is still going after `timeout`, it's killed
+/// along with every process it started.
+pub fn run_with_timeout(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> io::Result<Finished> {
+    // Only in its own process group when it has to be, so it can be killed
+    // as a whole. `group::Foreground` makes up for it no longer being in
+    // the terminal's foreground with us.
+    #[cfg(unix)]
+    if timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+
+        cmd.process_group(0);
+    }
+
+    let mut child =
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    let stdout = child.stdout.take().map(|out| tee(out, io::stdout));
+    let stderr = child.stderr.take().map(|err| tee(err, io::stderr));
+    let (status, timed_out) = match timeout {
+        Some(timeout) => {
+            #[cfg(unix)]
+            let _foreground =
+                group::Foreground::new(child.id() as libc::pid_t);
+
+            wait_for(&mut child, timeout)?
+        }
+        None => (child.wait()?, false),
+    };
+
+    let limit = timeout.map(|_| READ_AFTER_KILL);
+    Ok(Finished {
+        output: Output {
+            status,
+            stdout: finish(stdout, limit)?,
+            stderr: finish(stderr, limit)?,
+        },
+        timed_out,
+    })
+}
+
+/// Waits up to `timeout` for `child` to exit, killing it if it doesn't.
+/// Whatever else is left in its process group is killed either way.
+fn wait_for(
+    child: &mut Child,
+    timeout: Duration,
+) -> io::Result<(ExitStatus, bool)> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            // E.g. background processes that ignored a Ctrl-C, and would
+            // otherwise keep its output open.
+            kill_group(child);
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            kill_tree(child)?;
+            return Ok((child.wait()?, true));
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Kills `child` and, on Unix, the rest of the process group it leads.
+fn kill_tree(child: &mut Child) -> io::Result<()> {
+    if kill_group(child) {
+        return Ok(());
+    }
+
+    child.kill()
+}
+
+/// Kills the process group `child` leads, returning whether there was one.
+/// The group outlives `child` for as long as anything's left in it.
+fn kill_group(child: &Child) -> bool {
+    #[cfg(unix)]
+    {
+        // The group's ID is the child's, see `run_with_timeout`.
+        let group = -(child.id() as libc::pid_t);
+        unsafe { libc::kill(group, libc::SIGKILL) == 0 }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = child;
+        false
+    }
+}
+
+/// A thread copying a command's output to ours, see `tee`.
+struct Tee {
+    handle: JoinHandle<io::Result<()>>,
+    copy: Arc<Mutex<Vec<u8>>>,
+}
+
+fn tee<R, W>(mut source: R, sink: fn() -> W) -> Tee
+where
+    R: Read + Send + 'static,
+    W: Write + 'static,
+{
+    let copy = Arc::new(Mutex::new(Vec::new()));
+    let handle = thread::spawn({
+        let copy = Arc::clone(&copy);
+        move || {
+            let mut buf = [0; 8192];
+            loop {
+                let n = match source.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                // Keep going if our own output has gone away, e.g. when
+                // piped into `head`, so the child doesn't block and the
+                // copy is whole.
+                let mut sink = sink();
+                let _ = sink.write_all(&buf[..n]).and_then(|_| sink.flush());
+                if let Ok(mut copy) = copy.lock() {
+                    copy.extend_from_slice(&buf[..n]);
+                }
+            }
+        }
+    });
+
+    Tee { handle, copy }
+}
+
+/// Everything `tee` copied, once the output's closed or, with a `limit`,
+/// once that long has passed, whichever comes first. A thread that's still
+/// going after the `limit` is left to finish on its own.
+fn finish(tee: Option<Tee>, limit: Option<Duration>) -> io::Result<Vec<u8>> {
+    let tee = match tee {
+        Some(tee) => tee,
+        None => return Ok(Vec::new()),
+    };
+
+    if let Some(limit) = limit {
+        let deadline = Instant::now() + limit;
+        while !tee.handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    if limit.is_none() || tee.handle.is_finished() {
+        match tee.handle.join() {
+            Ok(result) => result?,
+            Err(_) => return Err(io::Error::other("Output thread panicked")),
+        }
+    }
+
+    let copy = match tee.copy.lock() {
+        Ok(copy) => copy.clone(),
+        Err(_) => return Err(io::Error::other("Output thread panicked")),
+    };
+
+    Ok(copy)
+}
+
+/// Keeping a command in its own process group from behaving differently to
+/// one in ours.
+#[cfg(unix)]
+mod group {
+    use std::mem;
+    use std::ptr;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The process group signals are passed on to, or 0 for none.
+    static GROUP: AtomicI32 = AtomicI32::new(0);
+
+    /// The signals asking spacework to stop, which should stop the command
+    /// too.
+    const FORWARDED: [libc::c_int; 4] =
+        [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+    extern "C" fn forward(signal: libc::c_int) {
+        let group = GROUP.load(Ordering::SeqCst);
+        if group > 0 {
+            unsafe {
+                libc::kill(-group, signal);
+            }
+        }
+    }
+
+    /// While it's alive, the process group it's for gets the terminal in
+    /// spacework's place, so it can read from it and gets its Ctrl-C, and
+    /// signals spacework gets asking it to stop are passed on to the group.
+    pub struct Foreground {
+        previous: Vec<(libc::c_int, libc::sigaction)>,
+        /// How SIGTTOU was handled, if the terminal was handed over.
+        ttou: Option<libc::sigaction>,
+    }
+
+    impl Foreground {
+        pub fn new(group: libc::pid_t) -> Self {
+            GROUP.store(group, Ordering::SeqCst);
+            let handler = forward as *const () as libc::sighandler_t;
+            let previous = FORWARDED
+                .iter()
+                .filter_map(|&signal| {
+                    Some((signal, set_handler(signal, handler)?))
+                })
+                .collect();
+
+            // Only if spacework has it, e.g. not when run in the background
+            // or with its input redirected.
+            let has_terminal = unsafe {
+                libc::isatty(libc::STDIN_FILENO) == 1
+                    && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+            };
+            let mut ttou = None;
+            if has_terminal {
+                // Without the terminal, taking it back would stop
+                // spacework with SIGTTOU.
+                ttou = set_handler(libc::SIGTTOU, libc::SIG_IGN);
+                unsafe {
+                    libc::tcsetpgrp(libc::STDIN_FILENO, group);
+                    // It may have been stopped for reading the terminal
+                    // before it had it.
+                    libc::kill(-group, libc::SIGCONT);
+                }
+            }
+
+            Foreground { previous, ttou }
+        }
+    }
+
+    impl Drop for Foreground {
+        fn drop(&mut self) {
+            if let Some(ttou) = self.ttou {
+                unsafe {
+                    libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+                    libc::sigaction(libc::SIGTTOU, &ttou, ptr::null_mut());
+                }
+            }
+            for (signal, previous) in self.previous.iter() {
+                unsafe {
+                    libc::sigaction(*signal, previous, ptr::null_mut());
+                }
+            }
+            GROUP.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Handles `signal` with `handler`, returning how it was handled.
+    fn set_handler(
+        signal: libc::c_int,
+        handler: libc::sighandler_t,
+    ) -> Option<libc::sigaction> {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handler;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous) != 0 {
+                return None;
+            }
+
+            Some(previous)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_copied_while_streaming() -> io::Result<()> {
+        let output = run(Command::new("sh").args([
+            "-c",
+            "echo out; echo err >&2; echo more; exit 3",
+        ]))?;
+
+        assert_eq!(output.stdout, b"out\nmore\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_kills_the_whole_process_tree() -> io::Result<()> {
+        let started = Instant::now();
+        let finished = run_with_timeout(
+            Command::new("sh").args(["-c", "sleep 10 & echo $!; wait"]),
+            Some(Duration::from_millis(200)),
+        )?;
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(finished.timed_out);
+        assert!(!finished.output.status.success());
+
+        assert_killed(&finished.output.stdout)?;
+
+        let finished = run_with_timeout(
+            &mut Command::new("true"),
+            Some(Duration::from_secs(10)),
+        )?;
+        assert!(!finished.timed_out);
+        assert!(finished.output.status.success());
+
+        Ok(())
+    }
+
+    #[test]
+    fn leftovers_killed_when_a_timed_command_exits() -> io::Result<()> {
+        let started = Instant::now();
+        let finished = run_with_timeout(
+            Command::new("sh").args(["-c", "sleep 10 & echo $!"]),
+            Some(Duration::from_secs(10)),
+        )?;
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!finished.timed_out);
+        assert!(finished.output.status.success());
+        assert_killed(&finished.output.stdout)?;
+
+        Ok(())
+    }
+
+    /// Checks the process with the ID in `pid` isn't running. Nothing may be
+    /// around to reap it, so it can still be listed, but as a zombie.
+    fn assert_killed(pid: &[u8]) -> io::Result<()> {
+        let pid = String::from_utf8_lossy(pid);
+        let state = Command::new("ps")
+            .args(["-o", "stat=", "-p", pid.trim()])
+            .output()?;
+        let state = String::from_utf8_lossy(&state.stdout);
+        assert!(
+            state.trim().is_empty() || state.starts_with('Z'),
+            "{} still running",
+            pid.trim()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_read_for_a_while_after_a_kill() -> io::Result<()> {
+        // Its own session keeps `sleep` out of the group that's killed, and
+        // holding the pipes open.
+        let started = Instant::now();
+        let finished = run_with_timeout(
+            Command::new("sh").args([
+                "-c",
+                "echo started; setsid sleep 3; sleep 10",
+            ]),
+            Some(Duration::from_millis(200)),
+        )?;
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(finished.timed_out);
+        assert_eq!(finished.output.stdout, b"started\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn exit_codes_passed_along() -> io::Result<()> {
+        let sh = |script: &str| Command::new("sh").args(["-c", script]).status();
+
+        assert_eq!(exit::code(sh("true")?), exit::SUCCESS);
+        assert_eq!(exit::code(sh("exit 42")?), 42);
+        assert_eq!(exit::code(sh("kill -TERM $$")?), 128 + 15);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,5 @@
 use crate::config::languagefile::LanguageFile;
 use crate::config::spaceworkfile::SpaceworkFile;
-use crate::spacework::history::History;
 
 use std::env::{self, VarError};
 use std::error::Error;
@@ -19,13 +18,9 @@ impl Workspace {
     ) -> Result<PathBuf, Box<dyn Error>> {
         let langfile = LanguageFile::from_language(lang)?;
 
-        let history = History::new()?;
         let workspace_root = workspace_dir()?;
         if !workspace_root.exists() {
             fs::create_dir_all(&workspace_root)?;
-            history.write(&format!(
-                "Created spacework directory: {}", &workspace_root.display()
-            ))?;
         }
 
         let proj_dir = create_proj_dir(&workspace_root, proj_name, &langfile)?;
@@ -67,11 +62,7 @@ fn create_proj_dir(
     if proj_dir.exists() {
         Err("Project directory already exists".into())
     } else {
-        let history = History::new()?;
         fs::create_dir_all(&proj_dir)?;
-        history.write(&format!(
-            "Created project directory: {}", &proj_dir.display())
-        )?;
 
         Ok(proj_dir)
     }
@@ -97,11 +88,27 @@ fn create_subdirs(
     Ok((src_dir, bin_dir))
 }
 
-pub fn build() -> Result<Output, Box<dyn Error>> {
+/// The `spacework.toml` and language file of the project containing the
+/// current directory.
+pub fn current_project(
+) -> Result<(SpaceworkFile, LanguageFile), Box<dyn Error>> {
     let cfg = SpaceworkFile::find_in_dir(&mut env::current_dir()?)?;
     let langfile = LanguageFile::from_language(&cfg.workspace.language)?;
 
-    langfile.build()
+    Ok((cfg, langfile))
+}
+
+/// Builds the current project. `jobs` overrides the project's `build.jobs`.
+pub fn build(
+    profile: &str,
+    jobs: Option<usize>,
+) -> Result<Option<Output>, Box<dyn Error>> {
+    let (mut cfg, langfile) = current_project()?;
+    if jobs.is_some() {
+        cfg.build.jobs = jobs;
+    }
+
+    langfile.build(&cfg, profile)
 }
 
 pub fn is_inside_workspace(path: &Path) -> Result<bool, Box<dyn Error>> {
@@ -139,13 +146,14 @@ pub fn delete_workspace() -> Result<(), Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
 
     #[test]
     #[should_panic]
     fn cfg_not_found_in_non_workspace_dir() {
-        let mut tmp = env::temp_dir();
+        let tmp = TempDir::new("outside").unwrap();
 
-        SpaceworkFile::find_in_dir(&mut tmp).unwrap();
+        SpaceworkFile::find_in_dir(&mut tmp.to_path_buf()).unwrap();
     }
 
     #[test]
@@ -161,7 +169,7 @@ mod tests {
 
     #[test]
     fn detects_inside_workspace_dir() -> Result<(), Box<dyn Error>> {
-        assert!(!is_inside_workspace(&env::temp_dir())?);
+        assert!(!is_inside_workspace(&TempDir::new("outside")?)?);
 
         assert!(is_inside_workspace(&workspace_dir()?)?);
 
@@ -0,0 +1,43 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A directory of a test's own in the system's temporary one. It's removed
+/// with everything in it when dropped, so also when the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory, with `name` in its name to tell whose it
+    /// is.
+    pub fn new(name: &str) -> io::Result<Self> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "spacework_{}_{}_{}",
+            name,
+            process::id(),
+            CREATED.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path)?;
+
+        Ok(TempDir(path))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}